@@ -0,0 +1,230 @@
+// batch.rs: Processes input files of the run directories in parallel, summary -
+// [20220406-BAR8TL]
+#![allow(unused)]
+
+use crate::fixvalues::*;
+use crate::inputs::inp_extsns;
+use crate::pagos2::{FilesumTp, Pagos2Tp};
+use crate::rblib::*;
+use crate::settings::SettingsTp;
+use std::fs;
+use std::cell::Cell;
+use std::panic;
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+thread_local! {
+  // Set while a worker processes one file: panics of the file are reported
+  // with it (messages and summary), not by the panic hook
+  static INFIL: Cell<bool> = const { Cell::new(false) };
+}
+
+// One input file to process: its settings (input path and output directory
+// set), stem and extension
+#[derive(Debug, Clone, Default)]
+pub struct InpfileTp {
+  pub s    : SettingsTp,
+  pub flnam: String,
+  pub flext: String
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BatchTp {
+  pub files: Vec<InpfileTp>,   // Input files found
+  pub fsums: Vec<FilesumTp>,   // Result of each file
+  pub nskip: usize             // Entries left out (not read, filtered out)
+}
+
+impl BatchTp {
+  pub fn new_batch() -> BatchTp {
+    BatchTp { ..Default::default() }
+  }
+
+  // Lists input files of the run directories (inpdr, several separated by ;),
+  // including subdirectories when recur is yes. Outputs of a file go to the
+  // same subdirectory under outdr, and under one more level named as the input
+  // directory when several are given. Input directories of the same name (e.g.
+  // a/input, b/input) get a sequence suffix there (input, input_2...)
+  pub fn list_files(&mut self, stg: &SettingsTp) {
+    let inpds: Vec<&str> = stg.inpdr.split(DIRSP).map(|d| d.trim())
+      .filter(|d| !d.is_empty()).collect();
+    let mut roots: Vec<String> = Vec::new();
+    for inpdr in &inpds {
+      let mut outdr = PathBuf::from(&stg.outdr);
+      if inpds.len() > 1 {
+        let root = Path::new(inpdr).file_name().and_then(|n| n.to_str())
+          .unwrap_or("");
+        let mut subdr = root.to_string();
+        let mut k = 1;
+        while roots.contains(&subdr) {
+          k += 1;
+          subdr = format!("{}_{}", root, k);
+        }
+        outdr.push(&subdr);
+        roots.push(subdr);
+      }
+      self.scan_dir(stg, Path::new(inpdr), &outdr);
+    }
+  }
+
+  // Directories under outdr are not scanned, neither error reports (_err), so
+  // outputs are never taken as inputs
+  fn scan_dir(&mut self, stg: &SettingsTp, inpdr: &Path, outdr: &Path) {
+    let outcn = fs::canonicalize(&stg.outdr).ok();
+    if let (Ok(dircn), Some(outcn)) = (fs::canonicalize(inpdr), outcn) {
+      if dircn.starts_with(&outcn) {
+        println!("Directory {} skipped, it is in the outputs directory",
+          inpdr.display());
+        self.nskip += 1;
+        return;
+      }
+    }
+    let entrs = match fs::read_dir(inpdr) {
+      Ok(entrs) => entrs,
+      Err(e)    => {
+        println!("Error: Directory {} not read: {}", inpdr.display(), e);
+        self.nskip += 1;
+        return;
+      },
+    };
+    let mut paths: Vec<PathBuf> = entrs.filter_map(|e| e.ok())
+      .map(|e| e.path()).collect();
+    paths.sort();
+    for entry in paths {
+      let flide = entry.file_name().and_then(|n| n.to_str()).unwrap_or("");
+      if entry.is_dir() {
+        if stg.recur == "yes" {
+          self.scan_dir(stg, &entry, &outdr.join(flide));
+        }
+        continue;
+      }
+      let flnam = entry.file_stem().and_then(|n| n.to_str()).unwrap_or("");
+      let flext = entry.extension().and_then(|n| n.to_str()).unwrap_or("");
+      if !inp_extsns(&stg.infmt).contains(&flext.to_lowercase().as_str()) ||
+        flnam.to_lowercase().ends_with(ERRSF) ||
+        (!stg.ifilt.is_empty() && !pass_filter(&stg.ifilt, flide)) {
+        self.nskip += 1;
+        continue;
+      }
+      let mut s = stg.clone();
+      s.inppt = entry.to_str().unwrap_or("").to_string();
+      s.outdr = outdr.display().to_string();
+      if !s.outdr.ends_with(['/', '\\']) {
+        s.outdr.push(MAIN_SEPARATOR);
+      }
+      s.seqnr = self.files.len() + 1;
+      if let Some(other) = self.files.iter().find(|f| f.s.outdr == s.outdr &&
+        out_stem(&f.s, &f.flnam) == out_stem(&s, flnam)) {
+        println!("Error: File {} skipped, its outputs would take the names of \
+          those of {} (outnm without seqnr)", s.inppt, other.s.inppt);
+        self.nskip += 1;
+        continue;
+      }
+      self.files.push(InpfileTp { s, flnam: flnam.to_string(),
+        flext: flext.to_string() });
+    }
+  }
+
+  // Processes the files listed with wrkrs workers (0 = one per CPU), each
+  // worker taking the next file not yet taken. Results and console messages
+  // are kept in the order of the list whatever the order files end, messages
+  // printed once all files are done. A file failing does not stop the batch,
+  // it is reported in the summary
+  pub fn proc_files(&mut self, wrkrs: usize) {
+    let wrkrs = if wrkrs > 0 { wrkrs } else {
+      thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+    };
+    let files = &self.files;
+    let nextf = AtomicUsize::new(0);
+    let rslts: Mutex<Vec<FilesumTp>> =
+      Mutex::new(vec![FilesumTp::default(); files.len()]);
+    let phook = Arc::new(panic::take_hook());
+    let chain = phook.clone();
+    panic::set_hook(Box::new(move |info| {
+      if !INFIL.with(|f| f.get()) {
+        chain(info);
+      }
+    }));
+    thread::scope(|sc| {
+      for _ in 0..wrkrs.min(files.len()) {
+        sc.spawn(|| loop {
+          let i = nextf.fetch_add(1, Ordering::SeqCst);
+          if i >= files.len() {
+            break;
+          }
+          let fsum = proc_file(&files[i]);
+          rslts.lock().unwrap()[i] = fsum;
+        });
+      }
+    });
+    drop(panic::take_hook()); // releases its reference to phook
+    if let Ok(phook) = Arc::try_unwrap(phook) {
+      panic::set_hook(phook);
+    }
+    self.fsums = rslts.into_inner().unwrap();
+    for f in &self.fsums {
+      println!("{}", f.inppt);
+      for messg in &f.cnsol {
+        println!("{}", messg);
+      }
+    }
+  }
+
+  pub fn print_summary(&self) {
+    let cnt = |stats: &str| self.fsums.iter().filter(|f| f.stats == stats)
+      .count();
+    println!("Batch summary ------------------------------------------------");
+    for f in &self.fsums {
+      println!("{:<8} {} - payments {}, DRs {}, warnings {}, errors {}{}",
+        f.stats, f.inppt, f.npays, f.ndocs, f.nwarn, f.nerrs,
+        if !f.messg.is_empty() { format!(": {}", f.messg) } else { "".into() });
+    }
+    println!("Files: {} processed, {} ok, {} aborted, {} failed, {} skipped",
+      self.fsums.len(), cnt(OK), cnt(ABRTD), cnt(FAILD), self.nskip);
+    println!("Payments: {}, DRs: {}, warnings: {}, errors: {}",
+      self.fsums.iter().map(|f| f.npays).sum::<usize>(),
+      self.fsums.iter().map(|f| f.ndocs).sum::<usize>(),
+      self.fsums.iter().map(|f| f.nwarn).sum::<usize>(),
+      self.fsums.iter().map(|f| f.nerrs).sum::<usize>());
+  }
+}
+
+// Processes one input file in fresh work areas. Panics while processing (file
+// not created, bad catalogs...) turn into a failed file, its partial outputs
+// removed
+pub fn proc_file(f: &InpfileTp) -> FilesumTp {
+  if let Err(e) = fs::create_dir_all(&f.s.outdr) {
+    return FilesumTp { inppt: f.s.inppt.clone(), stats: FAILD.to_string(),
+      messg: format!("Output directory {} not created: {}", f.s.outdr, e),
+      ..Default::default() };
+  }
+  let mut p = Pagos2Tp::new_pagos2();
+  p.hldms = true;
+  INFIL.with(|f| f.set(true));
+  let rslt = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+    p.proc_indiv_file(f.s.clone(), &f.flnam, &f.flext);
+    p.get_filesum()
+  }));
+  INFIL.with(|f| f.set(false));
+  match rslt {
+    Ok(fsum) => fsum,
+    Err(e)   => {
+      p.remove_outputs();
+      let messg = e.downcast_ref::<String>().cloned()
+        .or(e.downcast_ref::<&str>().map(|m| m.to_string()))
+        .unwrap_or("processing failed".to_string());
+      p.cnsol.push(format!("File {} failed: {}", f.s.inppt, messg));
+      FilesumTp { inppt: f.s.inppt.clone(), stats: FAILD.to_string(),
+        messg, cnsol: p.cnsol.clone(), ..Default::default() }
+    },
+  }
+}
+
+// Name (stem) of the outputs of one input file, company code taken as unknown
+// (same for all files), compared case-insensitive as in Windows folders
+fn out_stem(s: &SettingsTp, flnam: &str) -> String {
+  fmt_name(&s.outnm, &s.name_tokens(flnam, "-")).unwrap_or_default()
+    .to_lowercase()
+}
@@ -0,0 +1,202 @@
+// catalogs.rs: SAT catalogs for CFDI 4.0 and Pagos 2.0 codes ----------------
+// [20220406-BAR8TL]
+#![allow(unused)]
+
+use crate::fixvalues::{CUOTA, IEPS};
+use serde::Deserialize;
+use std::fs::File;
+
+// Built-in catalogs: c_FormaPago, c_Moneda (currencies most used in payments,
+// other codes only warned; the full catalog can be given in catalogs file),
+// c_Impuesto, c_TipoFactor and c_ObjetoImp
+pub const CATALOGS: &str = r#"
+{
+  "forma": [
+    { "code":"01", "desc":"Efectivo" },
+    { "code":"02", "desc":"Cheque nominativo" },
+    { "code":"03", "desc":"Transferencia electronica de fondos" },
+    { "code":"04", "desc":"Tarjeta de credito" },
+    { "code":"05", "desc":"Monedero electronico" },
+    { "code":"06", "desc":"Dinero electronico" },
+    { "code":"08", "desc":"Vales de despensa" },
+    { "code":"12", "desc":"Dacion en pago" },
+    { "code":"13", "desc":"Pago por subrogacion" },
+    { "code":"14", "desc":"Pago por consignacion" },
+    { "code":"15", "desc":"Condonacion" },
+    { "code":"17", "desc":"Compensacion" },
+    { "code":"23", "desc":"Novacion" },
+    { "code":"24", "desc":"Confusion" },
+    { "code":"25", "desc":"Remision de deuda" },
+    { "code":"26", "desc":"Prescripcion o caducidad" },
+    { "code":"27", "desc":"A satisfaccion del acreedor" },
+    { "code":"28", "desc":"Tarjeta de debito" },
+    { "code":"29", "desc":"Tarjeta de servicios" },
+    { "code":"30", "desc":"Aplicacion de anticipos" },
+    { "code":"31", "desc":"Intermediario pagos" },
+    { "code":"99", "desc":"Por definir" }
+  ],
+  "moned": [
+    { "code":"MXN", "desc":"Peso Mexicano",          "decim":2 },
+    { "code":"USD", "desc":"Dolar americano",        "decim":2 },
+    { "code":"EUR", "desc":"Euro",                   "decim":2 },
+    { "code":"CAD", "desc":"Dolar Canadiense",       "decim":2 },
+    { "code":"GBP", "desc":"Libra Esterlina",        "decim":2 },
+    { "code":"CHF", "desc":"Franco Suizo",           "decim":2 },
+    { "code":"CNY", "desc":"Yuan Renminbi",          "decim":2 },
+    { "code":"BRL", "desc":"Real brasileno",         "decim":2 },
+    { "code":"COP", "desc":"Peso Colombiano",        "decim":2 },
+    { "code":"ARS", "desc":"Peso Argentino",         "decim":2 },
+    { "code":"PEN", "desc":"Nuevo Sol",              "decim":2 },
+    { "code":"AUD", "desc":"Dolar Australiano",      "decim":2 },
+    { "code":"HKD", "desc":"Dolar de Hong Kong",     "decim":2 },
+    { "code":"SGD", "desc":"Dolar de Singapur",      "decim":2 },
+    { "code":"SEK", "desc":"Corona Sueca",           "decim":2 },
+    { "code":"NOK", "desc":"Corona Noruega",         "decim":2 },
+    { "code":"DKK", "desc":"Corona Danesa",          "decim":2 },
+    { "code":"INR", "desc":"Rupia India",            "decim":2 },
+    { "code":"JPY", "desc":"Yen",                    "decim":0 },
+    { "code":"KRW", "desc":"Won",                    "decim":0 },
+    { "code":"CLP", "desc":"Peso Chileno",           "decim":0 },
+    { "code":"CLF", "desc":"Unidad de Fomento",      "decim":4 },
+    { "code":"XXX", "desc":"Sin moneda",             "decim":0 }
+  ],
+  "impto": [
+    { "code":"001", "desc":"ISR",  "retnc":true, "trasl":false },
+    { "code":"002", "desc":"IVA",  "retnc":true, "trasl":true  },
+    { "code":"003", "desc":"IEPS", "retnc":true, "trasl":true  }
+  ],
+  "tipof": [
+    { "code":"Tasa"   },
+    { "code":"Cuota"  },
+    { "code":"Exento" }
+  ],
+  "objim": [
+    { "code":"01", "desc":"No objeto de impuesto" },
+    { "code":"02", "desc":"Si objeto de impuesto" },
+    { "code":"03", "desc":"Si objeto del impuesto y no obligado al desglose" },
+    { "code":"04", "desc":"Si objeto del impuesto y no causa impuesto" }
+  ]
+}"#;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CatentryTp {      // c_FormaPago, c_TipoFactor, c_ObjetoImp
+  pub code : String,
+  #[serde(default)]
+  pub desc : String
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MonedaTp {        // c_Moneda
+  pub code : String,
+  #[serde(default)]
+  pub desc : String,
+  #[serde(default)]
+  pub decim: u32             // Decimal positions
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ImpuestoTp {      // c_Impuesto
+  pub code : String,
+  #[serde(default)]
+  pub desc : String,
+  #[serde(default)]
+  pub retnc: bool,           // Can be retained
+  #[serde(default)]
+  pub trasl: bool            // Can be transferred
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CatalogsTp {
+  #[serde(default)]
+  pub forma: Vec<CatentryTp>,
+  #[serde(default)]
+  pub moned: Vec<MonedaTp>,
+  #[serde(default)]
+  pub impto: Vec<ImpuestoTp>,
+  #[serde(default)]
+  pub tipof: Vec<CatentryTp>,
+  #[serde(default)]
+  pub objim: Vec<CatentryTp>
+}
+
+impl CatalogsTp {
+  pub fn new_catalogs() -> CatalogsTp {
+    CatalogsTp{ ..Default::default() }
+  }
+
+  // Loads built-in catalogs and merges over them the entries of file catlg (if
+  // any). Entries in the file replace built-in entries having the same code.
+  pub fn get_catalogs(&mut self, catlg: &str) {
+    let ct: CatalogsTp = serde_json::from_str(CATALOGS).unwrap();
+    *self = ct;
+    if catlg.is_empty() {
+      return;
+    }
+    let f = File::open(catlg).unwrap_or_else(|e|
+      panic!("Catalogs file {} not found: {}", catlg, e));
+    let ext: CatalogsTp = serde_json::from_reader(f).unwrap_or_else(|e|
+      panic!("Catalogs file {} not well-formed: {}", catlg, e));
+    merge_entries(&mut self.forma, ext.forma, |e| e.code.clone());
+    merge_entries(&mut self.moned, ext.moned, |e| e.code.clone());
+    merge_entries(&mut self.impto, ext.impto, |e| e.code.clone());
+    merge_entries(&mut self.tipof, ext.tipof, |e| e.code.clone());
+    merge_entries(&mut self.objim, ext.objim, |e| e.code.clone());
+  }
+
+  pub fn seek_moneda(&self, code: &str) -> Option<&MonedaTp> {
+    self.moned.iter().find(|e| e.code == code)
+  }
+
+  pub fn seek_impuesto(&self, code: &str) -> Option<&ImpuestoTp> {
+    self.impto.iter().find(|e| e.code == code)
+  }
+
+  pub fn is_formapago(&self, code: &str) -> bool {
+    self.forma.iter().any(|e| e.code == code)
+  }
+
+  pub fn is_tipofactor(&self, code: &str) -> bool {
+    self.tipof.iter().any(|e| e.code == code)
+  }
+
+  pub fn is_objetoimp(&self, code: &str) -> bool {
+    self.objim.iter().any(|e| e.code == code)
+  }
+
+  // Returns decimal positions of one currency, dflt if currency is not listed
+  pub fn cur_decim(&self, curcy: &str, dflt: u32) -> u32 {
+    match self.seek_moneda(curcy) {
+      Some(mn) => mn.decim,
+      None     => dflt,
+    }
+  }
+
+  // Returns one message for each default code (konst) not found in catalogs
+  pub fn check_konst(&self, impto: &str, tipof: &str, objim: &str)
+    -> Vec<String> {
+    let mut errs: Vec<String> = Vec::new();
+    if self.seek_impuesto(impto).is_none() {
+      errs.push(format!("IMPUESTO '{}' not in c_Impuesto", impto));
+    }
+    if !self.is_tipofactor(tipof) {
+      errs.push(format!("TIPOFACTOR '{}' not in c_TipoFactor", tipof));
+    }
+    if tipof == CUOTA && impto != IEPS {
+      errs.push(format!("TIPOFACTOR '{}' only applies to IEPS", tipof));
+    }
+    if !self.is_objetoimp(objim) {
+      errs.push(format!("OBJETOIMPUESTO '{}' not in c_ObjetoImp", objim));
+    }
+    errs
+  }
+}
+
+// Entries of ext replace entries of base with the same key, others are added
+fn merge_entries<T>(base: &mut Vec<T>, ext: Vec<T>, key: fn(&T) -> String) {
+  for ee in ext {
+    match base.iter_mut().find(|e| key(e) == key(&ee)) {
+      Some(e) => { *e = ee;        },
+      None    => { base.push(ee); },
+    };
+  }
+}
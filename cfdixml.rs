@@ -0,0 +1,311 @@
+// cfdixml.rs: Writes unsigned CFDI 4.0 with Complemento de Pagos 2.0 XML -----
+// [20220406-BAR8TL]
+#![allow(unused)]
+
+use crate::fixvalues::*;
+use crate::pagos2::{LineTaxes, Pagos2Tp, TaxlineTp};
+use crate::rblib::*;
+use crate::rounding::*;
+use rust_decimal::Decimal;
+use std::fs::File;
+use std::io::Write;
+
+const XMLNS: [(&str, &str); 3] = [
+  ("xmlns:cfdi",   "http://www.sat.gob.mx/cfd/4"),
+  ("xmlns:pago20", "http://www.sat.gob.mx/Pagos20"),
+  ("xmlns:xsi",    "http://www.w3.org/2001/XMLSchema-instance")
+];
+const SCHLC: &str = "http://www.sat.gob.mx/cfd/4 \
+  http://www.sat.gob.mx/sitio_internet/cfd/4/cfdv40.xsd \
+  http://www.sat.gob.mx/Pagos20 \
+  http://www.sat.gob.mx/sitio_internet/cfd/Pagos/Pagos20.xsd";
+// Names suffix of Totales attributes per IVA rate
+const IVATOTS: [(Decimal, &str); 3] =
+  [(RAT16, "16"), (RAT08, "8"), (RAT00, "0")];
+// Attributes required by CFDI 4.0 and Pagos 2.0 schemas whose value comes from
+// config or input, so they can come empty (IdDocumento checked in validate)
+const REQATTRS: [(&str, &[&str]); 5] = [
+  ("cfdi:Comprobante",        &["LugarExpedicion"]),
+  ("cfdi:Emisor",             &["Rfc", "Nombre", "RegimenFiscal"]),
+  ("cfdi:Receptor",           &["Rfc", "Nombre", "DomicilioFiscalReceptor",
+                                "RegimenFiscalReceptor"]),
+  ("pago20:Pago",             &["FechaPago", "FormaDePagoP", "MonedaP"]),
+  ("pago20:DoctoRelacionado", &["MonedaDR", "NumParcialidad"])
+];
+
+#[derive(Debug, Clone, Default)]
+pub struct CfdixmlTp {
+  pub xml  : String,           // XML document being built
+  pub indnt: usize,            // Current indentation level
+  pub missg: Vec<String>       // Required attributes found empty
+}
+
+impl CfdixmlTp {
+  pub fn new_cfdixml() -> CfdixmlTp {
+    CfdixmlTp { ..Default::default() }
+  }
+
+  // Builds one comprobante of type "P" for the payment in process, returns
+  // one message for each required attribute left empty
+  pub fn build_cfdixml(&mut self, p: &Pagos2Tp) -> Vec<String> {
+    self.xml = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\r\n".to_string();
+    self.missg.clear();
+    self.build_comprobante(p);
+    self.missg.clone()
+  }
+
+  // Writes comprobante built as {xstem}.xml, returns the name of the file
+  // written
+  pub fn write_cfdixml(&self, p: &Pagos2Tp, xstem: &str) -> String {
+    let flnam = format!("{}{}.xml", p.s.outdr, xstem);
+    let mut xf = File::create(&flnam).expect("creation failed");
+    xf.write_all(self.xml.as_bytes()).expect("write failed");
+    flnam
+  }
+
+  fn build_comprobante(&mut self, p: &Pagos2Tp) {
+    let emisr = p.s.cfd.cfdix.seek_emisr(&p.lps[CMPNY]);
+    let recep = p.s.cfd.cfdix.seek_recep(&p.lps[CUSTO]);
+    let mut attrs: Vec<(&str, String)> = XMLNS.iter()
+      .map(|(n, v)| (*n, v.to_string())).collect();
+    attrs.extend([
+      ("xsi:schemaLocation", SCHLC.to_string()),
+      ("Version",            "4.0".to_string()),
+      ("Folio",              p.lps[DOCNM].clone()),
+      ("Fecha",              p.s.dtsys.format("%Y-%m-%dT%H:%M:%S").to_string()),
+      ("SubTotal",           "0".to_string()),
+      ("Moneda",             "XXX".to_string()),
+      ("Total",              "0".to_string()),
+      ("TipoDeComprobante",  "P".to_string()),
+      ("Exportacion",        "01".to_string()),
+      ("LugarExpedicion",    emisr.lugex.clone())
+    ]);
+    self.open_elem("cfdi:Comprobante", &attrs, false);
+    self.open_elem("cfdi:Emisor", &[
+      ("Rfc",           emisr.rfc),
+      ("Nombre",        emisr.nombr),
+      ("RegimenFiscal", emisr.regim)
+    ], true);
+    self.open_elem("cfdi:Receptor", &[
+      ("Rfc",                     recep.rfc),
+      ("Nombre",                  recep.nombr),
+      ("DomicilioFiscalReceptor", recep.domfi),
+      ("RegimenFiscalReceptor",   recep.regim),
+      ("UsoCFDI",                 "CP01".to_string())
+    ], true);
+    self.open_elem("cfdi:Conceptos", &[], false);
+    self.open_elem("cfdi:Concepto", &[
+      ("ClaveProdServ", "84111506".to_string()),
+      ("Cantidad",      "1".to_string()),
+      ("ClaveUnidad",   "ACT".to_string()),
+      ("Descripcion",   "Pago".to_string()),
+      ("ValorUnitario", "0".to_string()),
+      ("Importe",       "0".to_string()),
+      ("ObjetoImp",     "01".to_string())
+    ], true);
+    self.close_elem("cfdi:Conceptos");
+    self.open_elem("cfdi:Complemento", &[], false);
+    self.open_elem("pago20:Pagos", &[("Version", "2.0".to_string())], false);
+    self.build_totales(p);
+    self.build_pago(p);
+    self.close_elem("pago20:Pagos");
+    self.close_elem("cfdi:Complemento");
+    self.close_elem("cfdi:Comprobante");
+  }
+
+  // Totales: amounts in MXN, taken from payment total columns. Attributes go
+  // only for taxes present in the payment
+  fn build_totales(&mut self, p: &Pagos2Tp) {
+    let decmx = p.c.cur_decim(MXN, p.s.DECPS);
+    let mut attrs: Vec<(String, String)> = Vec::new();
+    for (impto, name, col) in [(IVA, "IVA", TRETN), (ISR, "ISR", TRISR),
+      (IEPS, "IEPS", TRIEP)] {
+      if p.ptx.iter().any(|g| g.clase == RETNC && g.impto == impto) {
+        attrs.push((format!("TotalRetenciones{}", name),
+          fmt_amount(p.lpf[col], decmx)));
+      }
+    }
+    for (clase, rate, _, ttb, tta) in IVACOLS {
+      if ttb == 0 || !p.ptx.iter().any(|g| g.clase == clase &&
+        g.impto == IVA && g.tipof == TASA && g.tasac == rate) {
+        continue;
+      }
+      let sufix = IVATOTS.iter().find(|(r, _)| *r == rate).unwrap().1;
+      attrs.push((format!("TotalTrasladosBaseIVA{}", sufix),
+        fmt_amount(p.lpf[ttb], decmx)));
+      attrs.push((format!("TotalTrasladosImpuestoIVA{}", sufix),
+        fmt_amount(p.lpf[tta], decmx)));
+    }
+    if p.ptx.iter().any(|g| g.clase == TRASL && g.impto == IVA &&
+      g.tipof == EXENT) {
+      attrs.push(("TotalTrasladosBaseIVAExento".to_string(),
+        fmt_amount(p.lpf[TTBEX], decmx)));
+    }
+    attrs.push(("MontoTotalPagos".to_string(),
+      fmt_amount(p.lpf[TPAYM], decmx)));
+    let attrs: Vec<(&str, String)> = attrs.iter()
+      .map(|(n, v)| (n.as_str(), v.clone())).collect();
+    self.open_elem("pago20:Totales", &attrs, true);
+  }
+
+  fn build_pago(&mut self, p: &Pagos2Tp) {
+    let decpy = p.c.cur_decim(&p.lps[CURCY], p.s.DECPS);
+    let tcamb = if p.lps[CURCY] == MXN
+      { "1".to_string() } else { fmt_amount(p.lpf[EXCHG], RTDEC) };
+    self.open_elem("pago20:Pago", &[
+      ("FechaPago",       xl_datetime(&p.lps[PYDTE])),
+      ("FormaDePagoP",    fmt_code(&p.lps[PYFRM])),
+      ("MonedaP",         p.lps[CURCY].clone()),
+      ("TipoCambioP",     tcamb),
+      ("Monto",           fmt_amount(p.lpf[AMOUN], decpy)),
+      ("NumOperacion",    p.lps[NUMOP].clone()),
+      ("RfcEmisorCtaOrd", p.lps[RFCOR].clone()),
+      ("NomBancoOrdExt",  p.lps[BNKOR].clone()),
+      ("CtaOrdenante",    p.lps[CTAOR].clone()),
+      ("RfcEmisorCtaBen", p.lps[RFCBF].clone()),
+      ("CtaBeneficiario", p.lps[CTABF].clone()),
+      ("TipoCadPago",     p.lps[PYTIP].clone()),
+      ("CertPago",        p.lps[PYCER].clone()),
+      ("CadPago",         p.lps[PYCAD].clone()),
+      ("SelloPago",       p.lps[PYSEL].clone())
+    ], false);
+    for i in 0..p.ipp {
+      self.build_docto(p, i);
+    }
+    self.build_impuestosp(p, decpy);
+    self.close_elem("pago20:Pago");
+  }
+
+  fn build_docto(&mut self, p: &Pagos2Tp, i: usize) {
+    let (lis, lif, ltx) = (&p.gis[i], &p.gif[i], &p.gtx[i]);
+    let decdr = p.c.cur_decim(&lis[CURCY], p.s.DECPS);
+    self.open_elem("pago20:DoctoRelacionado", &[
+      ("IdDocumento",      lis[ASGMT].trim().to_string()),
+      ("Folio",            lis[DOCNM].clone()),
+      ("MonedaDR",         lis[CURCY].clone()),
+      ("EquivalenciaDR",   lis[IEQUI].clone()),
+      ("NumParcialidad",   lis[PARTL].trim().to_string()),
+      ("ImpSaldoAnt",      fmt_amount(lif[PRVAM], decdr)),
+      ("ImpPagado",        fmt_amount(lif[PYAMT], decdr)),
+      ("ImpSaldoInsoluto", fmt_amount(lif[CURAM], decdr)),
+      ("ObjetoImpDR",      lis[IOBJI].clone())
+    ], ltx.is_empty());
+    if ltx.is_empty() {
+      return;
+    }
+    self.open_elem("pago20:ImpuestosDR", &[], false);
+    for (clase, group, elem) in [
+      (RETNC, "pago20:RetencionesDR", "pago20:RetencionDR"),
+      (TRASL, "pago20:TrasladosDR",   "pago20:TrasladoDR")] {
+      let taxes: LineTaxes = ltx.iter().filter(|tx| tx.clase == clase)
+        .cloned().collect();
+      if taxes.is_empty() {
+        continue;
+      }
+      self.open_elem(group, &[], false);
+      for tx in taxes {
+        self.open_elem(elem, &tax_attrs(&tx, "DR", decdr), true);
+      }
+      self.close_elem(group);
+    }
+    self.close_elem("pago20:ImpuestosDR");
+    self.close_elem("pago20:DoctoRelacionado");
+  }
+
+  // ImpuestosP: retenciones summarized by impuesto, traslados by impuesto,
+  // factor and rate (payment tax groups)
+  fn build_impuestosp(&mut self, p: &Pagos2Tp, decpy: u32) {
+    if p.ptx.is_empty() {
+      return;
+    }
+    self.open_elem("pago20:ImpuestosP", &[], false);
+    let mut retns: Vec<(String, Decimal)> = Vec::new();
+    for g in p.ptx.iter().filter(|g| g.clase == RETNC) {
+      match retns.iter_mut().find(|(impto, _)| *impto == g.impto) {
+        Some((_, impte)) => { *impte += g.impte; },
+        None             => { retns.push((g.impto.clone(), g.impte)); },
+      };
+    }
+    if !retns.is_empty() {
+      self.open_elem("pago20:RetencionesP", &[], false);
+      for (impto, impte) in retns {
+        self.open_elem("pago20:RetencionP", &[
+          ("ImpuestoP", impto),
+          ("ImporteP",  fmt_amount(impte, decpy))
+        ], true);
+      }
+      self.close_elem("pago20:RetencionesP");
+    }
+    let trsls: LineTaxes = p.ptx.iter().filter(|g| g.clase == TRASL)
+      .cloned().collect();
+    if !trsls.is_empty() {
+      self.open_elem("pago20:TrasladosP", &[], false);
+      for g in trsls {
+        self.open_elem("pago20:TrasladoP", &tax_attrs(&g, "P", decpy), true);
+      }
+      self.close_elem("pago20:TrasladosP");
+    }
+    self.close_elem("pago20:ImpuestosP");
+  }
+
+  // Opens one element; attributes with empty value are left out, and noted
+  // when required
+  fn open_elem(&mut self, name: &str, attrs: &[(&str, String)], empty: bool) {
+    self.xml.push_str(&"  ".repeat(self.indnt));
+    self.xml.push_str(&format!("<{}", name));
+    let reqrd = REQATTRS.iter().find(|(elem, _)| *elem == name)
+      .map(|(_, names)| *names).unwrap_or(&[]);
+    let folio = attrs.iter().find(|(attr, _)| *attr == "Folio")
+      .map(|(_, val)| format!(" (Folio {})", val)).unwrap_or_default();
+    for (attr, val) in attrs {
+      if !val.is_empty() {
+        self.xml.push_str(&format!(" {}=\"{}\"", attr, xml_escape(val)));
+      } else if reqrd.contains(attr) {
+        self.missg.push(format!("{} {} missing{}", name, attr, folio));
+      }
+    }
+    if empty {
+      self.xml.push_str("/>\r\n");
+    } else {
+      self.xml.push_str(">\r\n");
+      self.indnt += 1;
+    }
+  }
+
+  fn close_elem(&mut self, name: &str) {
+    self.indnt -= 1;
+    self.xml.push_str(&"  ".repeat(self.indnt));
+    self.xml.push_str(&format!("</{}>\r\n", name));
+  }
+}
+
+// Attributes Base, Impuesto, TipoFactor, TasaOCuota, Importe of one tax, names
+// with suffix DR or P. Exento traslados carry neither rate nor amount
+fn tax_attrs(tx: &TaxlineTp, sufix: &str, decps: u32)
+  -> Vec<(&'static str, String)> {
+  let names: [&'static str; 5] = if sufix == "DR" {
+    ["BaseDR", "ImpuestoDR", "TipoFactorDR", "TasaOCuotaDR", "ImporteDR"]
+  } else {
+    ["BaseP", "ImpuestoP", "TipoFactorP", "TasaOCuotaP", "ImporteP"]
+  };
+  let mut attrs = vec![
+    (names[0], fmt_amount(tx.base, decps)),
+    (names[1], tx.impto.clone()),
+    (names[2], tx.tipof.clone())
+  ];
+  if tx.tipof != EXENT {
+    attrs.push((names[3], fmt_amount(tx.tasac, RTDEC)));
+    attrs.push((names[4], fmt_amount(tx.impte, decps)));
+  }
+  attrs
+}
+
+// Amount with fixed decimal positions, zero included
+fn fmt_amount(val: Decimal, decps: u32) -> String {
+  format!("{:.*}", decps as usize, rb_round(val, decps))
+}
+
+fn xml_escape(val: &str) -> String {
+  val.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+    .replace('"', "&quot;").replace('\'', "&apos;")
+}
@@ -4,6 +4,9 @@
 #![allow(non_snake_case)]
 #![allow(non_camel_case_types)]
 
+use crate::catalogs::CatalogsTp;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
 use serde::Deserialize;
 use serde_json;
 use std::fs::File;
@@ -17,6 +20,16 @@ pub const IFILT: &str  = "!(*processed*)";                 // INPUTS_FILTER
 pub const INPNM: &str  = "dtsys'_'+inpfl'_inp_processed'"; // INPUTS_NAMING
 pub const OUTNM: &str  = "dtsys'_'+inpfl'_out'";           // OUTPUTS_NAMING
 pub const RENAM: &str  = "no";                             // RENAME_FILES
+pub const ITABL: &str  = "";                               // ITABLES_FILE
+pub const CATLG: &str  = "";                               // CATALOGS_FILE
+pub const INFMT: &str  = "auto";                           // INPUT_FORMAT
+pub const DELIM: &str  = "|";                              // INPUT_DELIMITER
+pub const ENCOD: &str  = "utf-8";                          // INPUT_ENCODING
+pub const ONERR: &str  = "ABORT";                          // ON_ERROR
+pub const DRLAY: &str  = "ROWS";                           // DR_TAXES_LAYOUT
+pub const DRMAX: &str  = "3";                              // DR_TAXES_MAXIMUM
+pub const OUTFM: &str  = "";                               // OUTPUT_FORMATS
+pub const OLAYT: &str  = "";                               // OUTPUT_LAYOUT
 pub const IMPTO: &str  = "002";                            // IMPUESTO
 pub const TIPOF: &str  = "Tasa";                           // TIPOFACTOR
 pub const OBJIM: &str  = "02";                             // OBJETOIMPUESTO
@@ -25,9 +38,73 @@ pub const TAB  : &str  = "edicom";                         // TAB
 pub const DEC  : &str  = "2";                              // DEC_POSITIONS
 pub const BATCH: &str  = "BATCH";                          // BATCH
 pub const INDIV: &str  = "INDIV";                          // INDIV
+pub const RECUR: &str  = "no";                             // RECURSIVE_SCAN
+pub const WRKRS: &str  = "0";                              // BATCH_WORKERS
+pub const DIRSP: char  = ';';                              // DIRS_SEPARATOR
+pub const OK   : &str  = "OK";                             // FILE_PROCESSED
+pub const ABRTD: &str  = "ABORTED";                        // FILE_ABORTED
+pub const FAILD: &str  = "FAILED";                         // FILE_FAILED
 pub const TITLE: &str  = "TITLE";                          // TITLE_LINE
 pub const PAYMT: &str  = "PAYMT";                          // PAYMENT_LINE
 pub const INVOI: &str  = "INVOI";                          // INVOICE_LINE
+pub const WILDC: &str  = "*";                              // ALL_COMPANIES
+pub const ABORT: &str  = "ABORT";                          // ABORT_FILE
+pub const SKIPP: &str  = "SKIP";                           // SKIP_PAYMENT
+pub const WARNG: &str  = "WARN";                           // WARN_CONTINUE
+pub const UNKDT: &str  = "UNKDT";                          // UNKNOWN_DOCTYPE
+pub const UNKTX: &str  = "UNKTX";                          // UNKNOWN_TAXCODE
+pub const TMTAX: &str  = "TMTAX";                          // TOO_MANY_DR_TAXES
+pub const MLIEP: &str  = "MLIEP";                          // SEVERAL_IEPS_RATES
+pub const ROWS : &str  = "ROWS";                           // DR_TAXES_IN_ROWS
+pub const COLS : &str  = "COLS";                           // DR_TAXES_IN_COLUMNS
+pub const XML  : &str  = "xml";                            // OUTPUT_CFDI_XML
+pub const JSON : &str  = "json";                           // OUTPUT_JSON
+pub const JSONL: &str  = "jsonl";                          // OUTPUT_JSON_LINES
+pub const ODELM: &str  = "|";                              // OUTPUT_DELIMITER
+pub const CRLF : &str  = "\r\n";                           // LINE_END_CRLF
+pub const LF   : &str  = "\n";                             // LINE_END_LF
+pub const CR   : &str  = "\r";                             // LINE_END_CR
+pub const AUTO : &str  = "auto";                           // INPUT_DETECTED
+pub const XLSX : &str  = "xlsx";                           // INPUT_EXCEL
+pub const XLS  : &str  = "xls";                            // INPUT_EXCEL_97
+pub const ODS  : &str  = "ods";                            // INPUT_OPENDOCUMENT
+pub const CSV  : &str  = "csv";                            // INPUT_CSV
+pub const EDICM: &str  = "edicom";                         // INPUT_EDICOM_FLAT
+pub const INFMTS: [&str; 6] = [AUTO, XLSX, XLS, ODS, CSV, EDICM];
+pub const ALLSH: &str  = "*";                              // ALL_WORKSHEETS
+pub const ERRSF: &str  = "_err";                           // ERRORS_FILE_SUFFIX
+pub const TOLER: &str  = "TOLER";                          // OUT_OF_TOLERANCE
+pub const NOEXR: &str  = "NOEXR";                          // NO_EXCHANGE_RATE
+pub const UNKCY: &str  = "UNKCY";                          // UNKNOWN_CURRENCY
+pub const MSCOL: &str  = "MSCOL";                          // MISSING_COLUMNS
+pub const NOQTY: &str  = "NOQTY";                          // CUOTA_NO_QUANTITY
+pub const FPDEF: &str  = "99";                             // FORMAPAGO_POR_DEFINIR
+pub const SUMPG: &str  = "SUMPG";                          // SUM_PAID_VS_MONTO
+pub const SALDO: &str  = "SALDO";                          // SALDO_INSOLUTO
+pub const PARCL: &str  = "PARCL";                          // NUM_PARCIALIDAD
+pub const FRMPG: &str  = "FRMPG";                          // FORMA_DE_PAGO
+pub const RFCOB: &str  = "RFCOB";                          // RFC_BANCO_ORDENANTE
+pub const RFCBB: &str  = "RFCBB";                          // RFC_BANCO_BENEFIC
+pub const CFDIA: &str  = "CFDIA";                          // CFDI_ATTRIB_MISSING
+pub const XSDPG: &str  = "XSDPG";                          // XSD_FACET_PAGO
+pub const IDDOC: &str  = "IDDOC";                          // ID_DOCUMENTO
+pub const DUPDN: &str  = "DUPDN";                          // DUPLICATE_PAYMENT_NR
+pub const RTDEC: u32   = 6;                                // RATE_DEC_POSITIONS
+pub const EQDEC: u32   = 10;                               // EQUIV_DEC_POSITIONS
+// Tax rates handled in payment-level columns
+pub const RAT16: Decimal = dec!(0.16);                     // RATE_16
+pub const RAT08: Decimal = dec!(0.08);                     // RATE_8
+pub const RAT00: Decimal = dec!(0.00);                     // RATE_0
+// Tax components
+pub const ISR  : &str  = "001";                            // IMPUESTO_ISR
+pub const IVA  : &str  = "002";                            // IMPUESTO_IVA
+pub const IEPS : &str  = "003";                            // IMPUESTO_IEPS
+pub const TASA : &str  = "Tasa";                           // FACTOR_TASA
+pub const CUOTA: &str  = "Cuota";                          // FACTOR_CUOTA
+pub const EXENT: &str  = "Exento";                         // FACTOR_EXENTO
+pub const TRASL: &str  = "T";                              // TRASLADO
+pub const RETNC: &str  = "R";                              // RETENCION
+pub const OBJSI: &str  = "02";                             // OBJETO_CON_DESGLOSE
 
 // Indexes for readibility - Position of Excel columns and Indexes for arrays
 // Source fields: Common to Payment lines (DZ) and Invoice lines (RV)
@@ -112,16 +189,44 @@ pub const PRI00: usize = 74; // retnc_impto_iva0_p
 pub const PRF00: usize = 75; // retnc_tipof_iva0_p
 pub const PRR00: usize = 76; // retnc_tasac_iva0_p
 pub const PRA00: usize = 77; // retnc_impor_iva0_p
-// Differences
+// Differences, both as sum paid to the DRs less amount declared for payment:
+// in MXN (DIFTL) and in payment currency (DIFPY)
 pub const DIFTL: usize = 78; // dif_monto_total_pagos
 pub const DIFPY: usize = 79; // dif_impor_pago
-// First invoice indicators
-pub const FIT16: usize =  0; // first_invo_trasl_iva16
-pub const FIR16: usize =  1; // first_invo_retnc_iva16
-pub const FIT08: usize =  2; // first_invo_trasl_iva8
-pub const FIR08: usize =  3; // first_invo_retnc_iva8
-pub const FIT00: usize =  4; // first_invo_trasl_iva0
-pub const FIR00: usize =  5; // first_invo_retnc_iva0
+// Total new fields for other taxes, amounts in MXN currency
+pub const TRISR: usize = 80; // retnc_isr
+pub const TRIEP: usize = 81; // retnc_ieps
+// Exempt fields: total (MXN) and payment (payment currency)
+pub const TTBEX: usize = 82; // trasl_basei_ivaex
+pub const PTBEX: usize = 83; // trasl_basei_ivaex_p
+pub const PTIEX: usize = 84; // trasl_impto_ivaex_p
+pub const PTFEX: usize = 85; // trasl_tipof_ivaex_p
+// IEPS traslado fields (amounts in payment currency)
+pub const PTBIE: usize = 86; // trasl_basei_ieps_p
+pub const PTIIE: usize = 87; // trasl_impto_ieps_p
+pub const PTFIE: usize = 88; // trasl_tipof_ieps_p
+pub const PTRIE: usize = 89; // trasl_tasac_ieps_p
+pub const PTAIE: usize = 90; // trasl_impor_ieps_p
+pub const NFLDS: usize = 91; // number_of_fields
+// DR amounts added up when lines of one invoice are grouped into one DR
+pub const SUMFLDS: [usize; 2] = [AMOUN, PYAMT];
+// DR fields repeated in continuation rows of DRs with several taxes
+pub const DRKEYS: [usize; 5] = [CMPNY, CUSTO, DOCNM, DOCTP, CURCY];
+// Input fields whose column must be in the input file
+pub const MANDCOLS: [usize; 13] = [CMPNY, CUSTO, DOCNM, DOCTP, PYDTE, AMOUN,
+  CURCY, EXCHG, PYFRM, PARTL, PRVAM, PYAMT, TAXCD];
+// Optional input column of units paid in invoice lines, base of Cuota taxes.
+// With no title row it comes next to the tax code
+pub const QTYTL: &str = "Quantity";
+// Payment IVA columns: clase, rate, first of 5 P columns, total base/importe
+pub const IVACOLS: [(&str, Decimal, usize, usize, usize); 6] = [
+  (TRASL, RAT16, PTB16, TTB16, TTA16),
+  (RETNC, RAT16, PRB16, 0,     0    ),
+  (TRASL, RAT08, PTB08, TTB08, TTA08),
+  (RETNC, RAT08, PRB08, 0,     0    ),
+  (TRASL, RAT00, PTB00, TTB00, TTA00),
+  (RETNC, RAT00, PRB00, 0,     0    )
+];
 // Internal tables - DocumentType, TaxCode
 pub const ITABLES: &str = r#"
 {
@@ -164,7 +269,9 @@ pub const ITABLES: &str = r#"
 pub struct FixvaluesTp {
   pub TT   : Vec<String>,
   pub NUMER: Vec<usize>,
-  pub ALPHA: Vec<usize>
+  pub ALPHA: Vec<usize>,
+  pub RATES: Vec<usize>,
+  pub TOTAL: Vec<usize>
 }
 
 impl FixvaluesTp {
@@ -259,6 +366,20 @@ impl FixvaluesTp {
     // Differences
     self.TT.push(String::from("Diff Monto Total Pagos"      )); // 78
     self.TT.push(String::from("Diff Importe Pago"           )); // 79
+    // Totals for other taxes titles
+    self.TT.push(String::from("Retenciones ISR"             )); // 80
+    self.TT.push(String::from("Retenciones IEPS"            )); // 81
+    // Exempt fields titles
+    self.TT.push(String::from("Traslados Base IVA Exento"   )); // 82
+    self.TT.push(String::from("P Traslado Base IVA Exento"  )); // 83
+    self.TT.push(String::from("P Traslado Impuesto IVA Ex"  )); // 84
+    self.TT.push(String::from("P Traslado TipoFactor IVA Ex")); // 85
+    // IEPS traslado fields titles
+    self.TT.push(String::from("P Traslado Base IEPS"        )); // 86
+    self.TT.push(String::from("P Traslado Impuesto IEPS"    )); // 87
+    self.TT.push(String::from("P Traslado TipoFactor IEPS"  )); // 88
+    self.TT.push(String::from("P Traslado TasaOCuota IEPS"  )); // 89
+    self.TT.push(String::from("P Traslado Importe IEPS"     )); // 90
     // Indexes for common numeric fields
     self.NUMER.push(AMOUN); //  6 = amount_doc_curr
     self.NUMER.push(PRVAM); // 12 = importe_saldo_anterior
@@ -269,43 +390,162 @@ impl FixvaluesTp {
     self.ALPHA.push(ITFAC); // 38 = trasl_tipof_dr
     self.ALPHA.push(IRIMP); // 42 = retnc_impto_dr
     self.ALPHA.push(IRFAC); // 43 = retnc_tipof_dr
-    self.ALPHA.push(IOBJI); // 46 = objeto_impto_dr
+    self.ALPHA.push(IEQUI); // 46 = equivalencia_dr
+    self.ALPHA.push(IOBJI); // 47 = objeto_impto_dr
     // Indexes for new payment alphanumeric fields
-    self.ALPHA.push(PTI16); // 48 = trasl_impto_iva16_p
-    self.ALPHA.push(PTF16); // 49 = trasl_tipof_iva16_p
-    self.ALPHA.push(PRI16); // 53 = retnc_impto_iva16_p
-    self.ALPHA.push(PRF16); // 54 = retnc_tipof_iva16_p
-    self.ALPHA.push(PTI08); // 58 = trasl_impto_iva8_p
-    self.ALPHA.push(PTF08); // 59 = trasl_tipof_iva8_p
-    self.ALPHA.push(PRI08); // 63 = retnc_impto_iva8_p
-    self.ALPHA.push(PRF08); // 64 = retnc_tipof_iva8_p
-    self.ALPHA.push(PTI00); // 68 = trasl_impto_iva0_p
-    self.ALPHA.push(PTF00); // 69 = trasl_tipof_iva0_p
-    self.ALPHA.push(PRI00); // 73 = retnc_impto_iva0_p
-    self.ALPHA.push(PRF00); // 74 = retnc_tipof_iva0_p
+    self.ALPHA.push(PTI16); // 49 = trasl_impto_iva16_p
+    self.ALPHA.push(PTF16); // 50 = trasl_tipof_iva16_p
+    self.ALPHA.push(PRI16); // 54 = retnc_impto_iva16_p
+    self.ALPHA.push(PRF16); // 55 = retnc_tipof_iva16_p
+    self.ALPHA.push(PTI08); // 59 = trasl_impto_iva8_p
+    self.ALPHA.push(PTF08); // 60 = trasl_tipof_iva8_p
+    self.ALPHA.push(PRI08); // 64 = retnc_impto_iva8_p
+    self.ALPHA.push(PRF08); // 65 = retnc_tipof_iva8_p
+    self.ALPHA.push(PTI00); // 69 = trasl_impto_iva0_p
+    self.ALPHA.push(PTF00); // 70 = trasl_tipof_iva0_p
+    self.ALPHA.push(PRI00); // 74 = retnc_impto_iva0_p
+    self.ALPHA.push(PRF00); // 75 = retnc_tipof_iva0_p
+    self.ALPHA.push(PTIEX); // 84 = trasl_impto_ivaex_p
+    self.ALPHA.push(PTFEX); // 85 = trasl_tipof_ivaex_p
+    self.ALPHA.push(PTIIE); // 87 = trasl_impto_ieps_p
+    self.ALPHA.push(PTFIE); // 88 = trasl_tipof_ieps_p
+    // Indexes for new rate fields (TasaOCuota)
+    self.RATES.push(ITRTE); // 39 = trasl_tasac_dr
+    self.RATES.push(IRRTE); // 44 = retnc_tasac_dr
+    self.RATES.push(PTR16); // 51 = trasl_tasac_iva16_p
+    self.RATES.push(PRR16); // 56 = retnc_tasac_iva16_p
+    self.RATES.push(PTR08); // 61 = trasl_tasac_iva8_p
+    self.RATES.push(PRR08); // 66 = retnc_tasac_iva8_p
+    self.RATES.push(PTR00); // 71 = trasl_tasac_iva0_p
+    self.RATES.push(PRR00); // 76 = retnc_tasac_iva0_p
+    self.RATES.push(PTRIE); // 89 = trasl_tasac_ieps_p
+    // Indexes for new total fields (amounts in MXN)
+    for i in TRETN..=TPAYM {
+      self.TOTAL.push(i);   // 28..35 = retnc_iva..monto_total_pagos
+    }
+    self.TOTAL.push(DIFTL); // 78 = dif_monto_total_pagos
+    self.TOTAL.push(TRISR); // 80 = retnc_isr
+    self.TOTAL.push(TRIEP); // 81 = retnc_ieps
+    self.TOTAL.push(TTBEX); // 82 = trasl_basei_ivaex
   }
 }
 
-// itables - Upload internal tables
+// itables - Upload internal tables (built-in defaults plus optional file)
 #[derive(Debug, Clone, Default, Deserialize)]
 pub struct DoctypesTp {
   pub code : String,
   pub dtype: String
 }
 
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TaxcompTp {
+  pub impto: String,         // Impuesto: 001 ISR, 002 IVA, 003 IEPS
+  pub tipof: String,         // TipoFactor: Tasa, Cuota, Exento
+  #[serde(default)]
+  pub tasac: Decimal,        // TasaOCuota
+  pub clase: String          // T = traslado, R = retencion
+}
+
 #[derive(Debug, Clone, Default, Deserialize)]
 pub struct TaxcodesTp {
   pub code : String,
-  pub trate: f32,
-  pub wrate: f32
+  #[serde(default)]
+  pub trate: Decimal,
+  #[serde(default)]
+  pub wrate: Decimal,
+  #[serde(default)]
+  pub exent: bool,           // exempt traslado of default impto
+  #[serde(default)]
+  pub objim: String,         // ObjetoImp, default from config if empty
+  #[serde(default)]
+  pub taxes: Vec<TaxcompTp>
+}
+
+impl TaxcodesTp {
+  // Tax components of the code. A code given only by trate/wrate stands for
+  // one traslado and (wrate not zero) one retencion of default impto/tipof,
+  // a code marked exent for one exempt traslado of default impto
+  pub fn get_taxes(&self, impto: &str, tipof: &str) -> Vec<TaxcompTp> {
+    if !self.taxes.is_empty() {
+      return self.taxes.clone();
+    }
+    let mut taxes: Vec<TaxcompTp> = Vec::new();
+    if self.exent {
+      taxes.push(TaxcompTp { impto: impto.to_string(), tipof: EXENT.to_string(),
+        tasac: Decimal::ZERO, clase: TRASL.to_string() });
+      return taxes;
+    }
+    taxes.push(TaxcompTp { impto: impto.to_string(), tipof: tipof.to_string(),
+      tasac: self.trate, clase: TRASL.to_string() });
+    if !self.wrate.is_zero() {
+      taxes.push(TaxcompTp { impto: impto.to_string(), tipof: tipof.to_string(),
+        tasac: self.wrate, clase: RETNC.to_string() });
+    }
+    taxes
+  }
+}
+
+impl TaxcompTp {
+  // Returns one message for each wrong attribute of the tax component
+  pub fn check_taxcomp(&self, cmpny: &str, taxcd: &str, cat: &CatalogsTp)
+    -> Vec<String> {
+    let mut errs: Vec<String> = Vec::new();
+    let whrat = format!("Company code {}, tax code {}, impuesto {}", cmpny,
+      taxcd, self.impto);
+    if self.clase != TRASL && self.clase != RETNC {
+      errs.push(format!("{}: clase '{}' not in {}/{}", whrat, self.clase,
+        TRASL, RETNC));
+    }
+    match cat.seek_impuesto(&self.impto) {
+      Some(im) => {
+        if self.clase == TRASL && !im.trasl {
+          errs.push(format!("{}: {} can only be retained", whrat, im.desc));
+        }
+        if self.clase == RETNC && !im.retnc {
+          errs.push(format!("{}: {} cannot be retained", whrat, im.desc));
+        }
+      },
+      None     => {
+        errs.push(format!("{}: impuesto not in c_Impuesto", whrat));
+      },
+    };
+    if !cat.is_tipofactor(&self.tipof) {
+      errs.push(format!("{}: tipof '{}' not in c_TipoFactor", whrat,
+        self.tipof));
+    }
+    if self.tipof == EXENT && (self.clase != TRASL || !self.tasac.is_zero()) {
+      errs.push(format!("{}: exempt only as traslado with no tasac", whrat));
+    }
+    if self.tipof == CUOTA && self.impto != IEPS {
+      errs.push(format!("{}: tipof {} only applies to IEPS", whrat, CUOTA));
+    }
+    if self.tasac < Decimal::ZERO ||
+      (self.tipof == TASA && self.tasac >= Decimal::ONE) {
+      errs.push(format!("{}: tasac {} out of range", whrat, self.tasac));
+    }
+    errs
+  }
 }
 
 #[derive(Debug, Clone, Default, Deserialize)]
 pub struct CompanycodesTp {
   pub code : String,
+  #[serde(default)]
   pub desc : String,
+  #[serde(default)]
   pub doctp: Vec<DoctypesTp>,
-  pub taxcd: Vec<TaxcodesTp>
+  #[serde(default)]
+  pub taxcd: Vec<TaxcodesTp>,
+  #[serde(default = "dflt_inhrt")]
+  pub inhrt: bool,           // inherit codes from "*" entry
+  #[serde(default)]
+  pub dsdoc: Vec<String>,    // "*" document types disabled for company
+  #[serde(default)]
+  pub dstax: Vec<String>     // "*" tax codes disabled for company
+}
+
+fn dflt_inhrt() -> bool {
+  true
 }
 
 #[derive(Debug, Clone, Default, Deserialize)]
@@ -319,66 +559,186 @@ impl ItablesTp {
     it
   }
 
-  pub fn get_itables(&mut self) {
+  // Loads built-in tables and merges over them the entries of file itabl (if
+  // any). Entries in the file replace built-in entries having the same code.
+  pub fn get_itables(&mut self, itabl: &str, cat: &CatalogsTp) {
     let it: ItablesTp = serde_json::from_str(ITABLES).unwrap();
     self.comco = it.comco;
+    if itabl.is_empty() {
+      return;
+    }
+    let f = File::open(itabl).unwrap_or_else(|e|
+      panic!("Internal tables file {} not found: {}", itabl, e));
+    let ext: ItablesTp = serde_json::from_reader(f).unwrap_or_else(|e|
+      panic!("Internal tables file {} not well-formed: {}", itabl, e));
+    let errs = ext.check_itables(cat);
+    if !errs.is_empty() {
+      panic!("Error: Internal tables file {} not valid\n{}", itabl,
+        errs.join("\n"));
+    }
+    self.merge_itables(ext);
   }
 
-  pub fn seek_doctp(&mut self, cmpny: &String, doctp: &String) -> String {
-    let mut dtype: &str = "";
-    let mut found: bool = false;
+  // Returns one message for each invalid entry, naming company and code
+  pub fn check_itables(&self, cat: &CatalogsTp) -> Vec<String> {
+    let mut errs: Vec<String> = Vec::new();
+    let mut ccods: Vec<&str> = Vec::new();
     for cc in &self.comco {
-      if cc.code.as_str() == "*" {
-        for tp in &cc.doctp {
-          if tp.code == *doctp {
-            dtype = tp.dtype.as_str();
-            break;
-          }
+      if cc.code.is_empty() {
+        errs.push(format!("Company code entry '{}' has no code", cc.desc));
+        continue;
+      }
+      if ccods.contains(&cc.code.as_str()) {
+        errs.push(format!("Company code {} is duplicated", cc.code));
+      }
+      ccods.push(cc.code.as_str());
+      let mut dcods: Vec<&str> = Vec::new();
+      for tp in &cc.doctp {
+        if tp.code.is_empty() {
+          errs.push(format!("Company code {}: document type with no code",
+            cc.code));
+          continue;
+        }
+        if dcods.contains(&tp.code.as_str()) {
+          errs.push(format!("Company code {}, document type {}: duplicated",
+            cc.code, tp.code));
+        }
+        dcods.push(tp.code.as_str());
+        if tp.dtype != TITLE && tp.dtype != PAYMT && tp.dtype != INVOI {
+          errs.push(format!(
+            "Company code {}, document type {}: dtype '{}' not in {}/{}/{}",
+            cc.code, tp.code, tp.dtype, TITLE, PAYMT, INVOI));
         }
       }
-      if cc.code == *cmpny {
-        for tp in &cc.doctp {
-          if tp.code.as_str() == *doctp {
-            dtype = tp.dtype.as_str();
-            found = true;
-            break;
-          }
+      let mut tcods: Vec<&str> = Vec::new();
+      for tc in &cc.taxcd {
+        if tc.code.is_empty() {
+          errs.push(format!("Company code {}: tax code with no code",
+            cc.code));
+          continue;
+        }
+        if tcods.contains(&tc.code.as_str()) {
+          errs.push(format!("Company code {}, tax code {}: duplicated",
+            cc.code, tc.code));
+        }
+        tcods.push(tc.code.as_str());
+        if tc.trate < Decimal::ZERO || tc.trate >= Decimal::ONE {
+          errs.push(format!("Company code {}, tax code {}: trate {} not in 0..1",
+            cc.code, tc.code, tc.trate));
+        }
+        if tc.wrate < Decimal::ZERO || tc.wrate >= Decimal::ONE {
+          errs.push(format!("Company code {}, tax code {}: wrate {} not in 0..1",
+            cc.code, tc.code, tc.wrate));
+        }
+        for tx in &tc.taxes {
+          errs.extend(tx.check_taxcomp(&cc.code, &tc.code, cat));
+        }
+        if !tc.taxes.is_empty() &&
+          (!tc.trate.is_zero() || !tc.wrate.is_zero()) {
+          errs.push(format!(
+            "Company code {}, tax code {}: trate/wrate and taxes both given",
+            cc.code, tc.code));
+        }
+        if !tc.objim.is_empty() && !cat.is_objetoimp(&tc.objim) {
+          errs.push(format!(
+            "Company code {}, tax code {}: objim '{}' not in c_ObjetoImp",
+            cc.code, tc.code, tc.objim));
+        }
+        if tc.exent && (!tc.taxes.is_empty() || !tc.trate.is_zero()) {
+          errs.push(format!(
+            "Company code {}, tax code {}: exent with trate or taxes given",
+            cc.code, tc.code));
+        }
+        if cc.dstax.contains(&tc.code) {
+          errs.push(format!("Company code {}, tax code {}: defined and disabled",
+            cc.code, tc.code));
         }
       }
-      if found {
-        break;
+      if cc.code == WILDC && (!cc.dsdoc.is_empty() || !cc.dstax.is_empty()) {
+        errs.push(format!("Company code {}: wildcard cannot disable codes",
+          cc.code));
       }
     }
-    return dtype.to_string();
+    errs
   }
 
-  pub fn seek_taxcd(&mut self, cmpny: &String, taxcd: &String) -> TaxcodesTp {
-    let mut txdta: TaxcodesTp = Default::default();
-    let mut found: bool = false;
-    for cc in &self.comco {
-      if cc.code.as_str() == "*" {
-        for tc in &cc.taxcd {
-          if tc.code == *taxcd {
-            let txc = (*tc).clone();
-            txdta = txc;
-            break;
+  // Merges company codes of ext into current tables, entry by entry
+  fn merge_itables(&mut self, ext: ItablesTp) {
+    for ec in ext.comco {
+      match self.comco.iter_mut().find(|cc| cc.code == ec.code) {
+        Some(cc) => {
+          if !ec.desc.is_empty() {
+            cc.desc = ec.desc;
+          }
+          cc.inhrt = ec.inhrt;
+          cc.dsdoc.extend(ec.dsdoc);
+          cc.dstax.extend(ec.dstax);
+          for etp in ec.doctp {
+            match cc.doctp.iter_mut().find(|tp| tp.code == etp.code) {
+              Some(tp) => { *tp = etp;          },
+              None     => { cc.doctp.push(etp); },
+            };
           }
+          for etc in ec.taxcd {
+            match cc.taxcd.iter_mut().find(|tc| tc.code == etc.code) {
+              Some(tc) => { *tc = etc;          },
+              None     => { cc.taxcd.push(etc); },
+            };
+          }
+        },
+        None => { self.comco.push(ec); },
+      };
+    }
+  }
+
+  // Resolution order: company-specific entry first, then the "*" wildcard
+  // entry, unless the company stops inheritance of that code (or of all codes)
+  pub fn seek_doctp(&self, cmpny: &String, doctp: &String) -> String {
+    if let Some(cc) = self.seek_comco(cmpny) {
+      for tp in &cc.doctp {
+        if tp.code == *doctp {
+          return tp.dtype.clone();
         }
       }
-      if cc.code == *cmpny {
-        for tc in &cc.taxcd {
-          if tc.code.as_str() == taxcd {
-            let txc = (*tc).clone();
-            txdta = txc;
-            found = true;
-            break;
-          }
+      if !cc.inhrt || cc.dsdoc.contains(doctp) {
+        return "".to_string();
+      }
+    }
+    if let Some(cc) = self.seek_comco(&WILDC.to_string()) {
+      for tp in &cc.doctp {
+        if tp.code == *doctp {
+          return tp.dtype.clone();
+        }
+      }
+    }
+    "".to_string()
+  }
+
+  pub fn seek_taxcd(&self, cmpny: &String, taxcd: &String)
+    -> Option<TaxcodesTp> {
+    if let Some(cc) = self.seek_comco(cmpny) {
+      for tc in &cc.taxcd {
+        if tc.code == *taxcd {
+          return Some(tc.clone());
         }
       }
-      if found {
-        break;
+      if !cc.inhrt || cc.dstax.contains(taxcd) {
+        return None;
       }
     }
-    return txdta;
+    if let Some(cc) = self.seek_comco(&WILDC.to_string()) {
+      for tc in &cc.taxcd {
+        if tc.code == *taxcd {
+          return Some(tc.clone());
+        }
+      }
+    }
+    None
+  }
+
+  // Block of one company code as written in the tables: a company code, or
+  // WILDC for the wildcard block
+  fn seek_comco(&self, cmpny: &String) -> Option<&CompanycodesTp> {
+    self.comco.iter().find(|cc| cc.code == *cmpny)
   }
 }
@@ -0,0 +1,225 @@
+// inputs.rs: Input readers giving rows of cells for Pagos 1.0 EDICOM data ----
+// [20220406-BAR8TL]
+#![allow(unused)]
+
+use crate::fixvalues::*;
+use crate::settings::SettingsTp;
+use calamine::DataType;
+use calamine::{Ods, Reader, Xls, Xlsx, open_workbook};
+use encoding_rs::Encoding;
+use std::fs;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+// Extensions recognized for each input format
+pub const INPEXTS: [(&str, &str); 7] = [
+  ("xlsx", XLSX), ("xlsm", XLSX), ("xls", XLS), ("ods", ODS), ("csv", CSV),
+  ("lot", EDICM), ("txt", EDICM)
+];
+
+// Input rows: row number in the source (Excel row, text line) and cells
+pub type InputRows = Vec<(usize, Vec<DataType>)>;
+
+// Input rows of each worksheet read, one block only for text files
+pub type InputSheets = Vec<InputRows>;
+
+// Source of input rows. Each row comes with the cells of all its columns, as
+// consumed by get_linefields (columns matched to fields by their titles).
+// Blocks keep apart rows of different worksheets, as each one has its own
+// columns
+pub trait Rowsource {
+  fn read_rows(&mut self) -> Result<InputSheets, String>;
+}
+
+// Spreadsheet workbook (xlsx, xls or ods), rows of the selected worksheets.
+// Selectors, separated by comma: sheet name, #n for n-th sheet (#1 = first
+// sheet) or * for all sheets. Rows of each sheet go in a block of their own,
+// title row included
+pub struct WorkbooksrcTp {
+  pub inppt: String,           // Input file path
+  pub wbtyp: String,           // Workbook type: xlsx, xls, ods
+  pub sheet: String            // Worksheet selectors
+}
+
+impl Rowsource for WorkbooksrcTp {
+  fn read_rows(&mut self) -> Result<InputSheets, String> {
+    match self.wbtyp.as_str() {
+      XLS => read_sheets::<Xls<_>>(&self.inppt, &self.sheet),
+      ODS => read_sheets::<Ods<_>>(&self.inppt, &self.sheet),
+        _ => read_sheets::<Xlsx<_>>(&self.inppt, &self.sheet),
+    }
+  }
+}
+
+fn read_sheets<R>(inppt: &str, sheet: &str) -> Result<InputSheets, String>
+  where R: Reader<RS = BufReader<File>> {
+  let mut wbook: R = open_workbook(inppt)
+    .map_err(|e| format!("Workbook {} not opened: {:?}", inppt, e))?;
+  let names = wbook.sheet_names();
+  let tabs = sel_sheets(&names, sheet).map_err(|e| format!("{} in workbook \
+    {}, available sheets: {}", e, inppt, names.join(", ")))?;
+  let mut shets: InputSheets = Vec::new();
+  for tab in tabs {
+    let r = match wbook.worksheet_range(&tab) {
+      Some(Ok(r))  => r,
+      Some(Err(e)) => return Err(format!("Worksheet {} not read: {:?}", tab,
+        e)),
+      None         => return Err(format!("Worksheet {} not found", tab)),
+    };
+    // Range starts at first used cell, not at Excel row 1
+    let rstrt = r.start().map(|(row, _)| row as usize).unwrap_or(0);
+    shets.push(r.rows().enumerate().map(|(k, row)| (rstrt + k + 1,
+      row.to_vec())).collect());
+  }
+  Ok(shets)
+}
+
+// Resolves worksheet selectors into sheet names of the workbook
+fn sel_sheets(names: &[String], sheet: &str)
+  -> Result<Vec<String>, String> {
+  let mut tabs: Vec<String> = Vec::new();
+  for selec in sheet.split(',').map(|t| t.trim()).filter(|t| !t.is_empty()) {
+    if selec == ALLSH {
+      tabs.extend(names.iter().cloned());
+    } else if let Some(n) = selec.strip_prefix('#')
+      .and_then(|n| n.parse::<usize>().ok()) {
+      match names.get(n.wrapping_sub(1)) {
+        Some(name) => tabs.push(name.clone()),
+        None       => return Err(format!("Worksheet {} not found", selec)),
+      };
+    } else if names.iter().any(|name| name == selec) {
+      tabs.push(selec.to_string());
+    } else {
+      return Err(format!("Worksheet {} not found", selec));
+    }
+  }
+  if tabs.is_empty() {
+    return Err(format!("No worksheet selected by '{}'", sheet));
+  }
+  Ok(tabs)
+}
+
+// EDICOM Pagos 1.0 flat file (.lot/.txt): one line per row, fields separated
+// by delim, text in encoding encod. Empty fields come as empty cells
+pub struct EdicomsrcTp {
+  pub inppt: String,           // Input file path
+  pub delim: String,           // Fields delimiter
+  pub encod: String            // Text encoding label (utf-8, windows-1252...)
+}
+
+impl Rowsource for EdicomsrcTp {
+  fn read_rows(&mut self) -> Result<InputSheets, String> {
+    let text = read_text(&self.inppt, &self.encod)?;
+    let mut rows: InputRows = Vec::new();
+    for (k, line) in text.lines().enumerate() {
+      if line.trim().is_empty() {
+        continue;
+      }
+      rows.push((k + 1, line.split(self.delim.as_str())
+        .map(txt_cell).collect()));
+    }
+    Ok(vec![rows])
+  }
+}
+
+// CSV export: quoted fields allowed, delimiter (comma, semicolon or tab) taken
+// from the title line, text in encoding encod
+pub struct CsvsrcTp {
+  pub inppt: String,           // Input file path
+  pub encod: String            // Text encoding label
+}
+
+impl Rowsource for CsvsrcTp {
+  fn read_rows(&mut self) -> Result<InputSheets, String> {
+    let text = read_text(&self.inppt, &self.encod)?;
+    let title = text.lines().next().unwrap_or("");
+    let delim = [b',', b';', b'\t'].iter()
+      .max_by_key(|d| title.matches(**d as char).count()).cloned().unwrap();
+    let mut rdr = csv::ReaderBuilder::new().has_headers(false).flexible(true)
+      .delimiter(delim).from_reader(text.as_bytes());
+    let mut rows: InputRows = Vec::new();
+    for rcd in rdr.records() {
+      let rcd = rcd.map_err(|e| format!("File {} not read: {}", self.inppt,
+        e))?;
+      if rcd.iter().all(|f| f.trim().is_empty()) {
+        continue;
+      }
+      // Line of the record, counted on the text: the reader skips blank
+      // lines and may place a record on the LF of a previous CRLF
+      let bytnr = rcd.position().map(|p| p.byte() as usize + 1).unwrap_or(0)
+        .min(text.len());
+      let rownr = text.as_bytes()[..bytnr].iter().filter(|b| **b == b'\n')
+        .count() + 1;
+      rows.push((rownr, rcd.iter().map(txt_cell).collect()));
+    }
+    Ok(vec![rows])
+  }
+}
+
+// Reads a text file decoding it from encoding encod
+fn read_text(inppt: &str, encod: &str) -> Result<String, String> {
+  let bytes = fs::read(inppt)
+    .map_err(|e| format!("File {} not read: {}", inppt, e))?;
+  let encdg = Encoding::for_label(encod.as_bytes())
+    .ok_or(format!("Encoding {} not known", encod))?;
+  let (text, _, malfd) = encdg.decode(&bytes);
+  if malfd {
+    return Err(format!("File {} has characters not valid in {}", inppt,
+      encod));
+  }
+  Ok(text.into_owned())
+}
+
+fn txt_cell(f: &str) -> DataType {
+  if f.is_empty() { DataType::Empty } else { DataType::String(f.to_string()) }
+}
+
+// Input format of the file: the one set for the run, or when auto, the one of
+// its extension, or at last the one its content looks like
+pub fn det_format(s: &SettingsTp) -> String {
+  if s.infmt != AUTO {
+    return s.infmt.clone();
+  }
+  let extsn = Path::new(&s.inppt).extension().and_then(|e| e.to_str())
+    .unwrap_or("").to_lowercase();
+  if let Some((_, infmt)) = INPEXTS.iter().find(|(ext, _)| *ext == extsn) {
+    return infmt.to_string();
+  }
+  let mut head = [0u8; 512];
+  let nread = File::open(&s.inppt).and_then(|mut f| f.read(&mut head))
+    .unwrap_or(0);
+  let head = &head[..nread];
+  if head.starts_with(b"PK\x03\x04") { // zip container: ods or xlsx
+    let ods = head.windows(12).any(|w| w == b"opendocument");
+    return if ods { ODS.to_string() } else { XLSX.to_string() };
+  }
+  if head.starts_with(b"\xD0\xCF\x11\xE0") { // OLE container: xls
+    return XLS.to_string();
+  }
+  let line = head.split(|b| *b == b'\n').next().unwrap_or(&[]);
+  if line.windows(s.delim.len().max(1)).any(|w| w == s.delim.as_bytes()) {
+    EDICM.to_string()
+  } else {
+    CSV.to_string()
+  }
+}
+
+// Reader for the input format of the file
+pub fn new_rowsource(s: &SettingsTp) -> Box<dyn Rowsource> {
+  match det_format(s).as_str() {
+    EDICM => Box::new(EdicomsrcTp { inppt: s.inppt.clone(),
+      delim: s.delim.clone(), encod: s.encod.clone() }),
+    CSV   => Box::new(CsvsrcTp { inppt: s.inppt.clone(),
+      encod: s.encod.clone() }),
+    infmt => Box::new(WorkbooksrcTp { inppt: s.inppt.clone(),
+      wbtyp: infmt.to_string(), sheet: s.sheet.clone() }),
+  }
+}
+
+// File extensions accepted for one input format. When auto, all of them but
+// the EDICOM ones (.lot/.txt), as the outputs written are such files too
+pub fn inp_extsns(infmt: &str) -> Vec<&'static str> {
+  INPEXTS.iter().filter(|(_, f)| *f == infmt || (infmt == AUTO && *f != EDICM))
+    .map(|(ext, _)| *ext).collect()
+}
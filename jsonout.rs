@@ -0,0 +1,199 @@
+// jsonout.rs: Writes computed payments as JSON document or JSON Lines ------
+// [20220406-BAR8TL]
+#![allow(unused)]
+//
+// Schema version 1.0. Amounts and rates are decimal strings, rounded as in the
+// .lot file (amounts to decimals of their currency, rates to 6 decimals).
+//
+// JSON document ({file}.json):
+//   { "versn": "1.0",          schema version
+//     "sourc": "file",         input file name (stem)
+//     "pagos": [ pago, ... ] }
+// JSON Lines ({file}.jsonl): one pago per line.
+//
+// pago:
+//   versn  schema version      cmpny  company code      custo  customer
+//   docnm  payment document    fecha  FechaPago         forma  FormaDePagoP
+//   moned  MonedaP             tcamb  TipoCambioP       monto  Monto
+//   numop  NumOperacion        rfcor  RfcEmisorCtaOrd   bnkor  NomBancoOrdExt
+//   ctaor  CtaOrdenante        rfcbf  RfcEmisorCtaBen   ctabf  CtaBeneficiario
+//   totls  Totales, amounts in MXN:
+//          retiv/retis/retie   TotalRetenciones IVA/ISR/IEPS
+//          tb16/ti16, tb08/ti08, tb00/ti00   TotalTraslados Base/Impuesto IVA
+//          tbexe               TotalTrasladosBaseIVAExento
+//          monto               MontoTotalPagos
+//   impts  ImpuestosP, tax groups in payment currency: [ tax, ... ]
+//   doctos DoctoRelacionado: [ docto, ... ]
+// docto:
+//   iddoc  IdDocumento         docnm  Folio             moned  MonedaDR
+//   equiv  EquivalenciaDR      parcl  NumParcialidad    salda  ImpSaldoAnt
+//   pagad  ImpPagado           salin  ImpSaldoInsoluto  objim  ObjetoImpDR
+//   taxcd  tax codes of the invoice lines grouped in the DR
+//   impts  ImpuestosDR, in invoice currency: [ tax, ... ]
+// tax:
+//   clase  T traslado / R retencion   impto  Impuesto   tipof  TipoFactor
+//   tasac  TasaOCuota          base   Base              impte  Importe
+
+use crate::fixvalues::*;
+use crate::pagos2::{LineTaxes, Pagos2Tp, TaxlineTp};
+use crate::rblib::*;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Write;
+
+pub const JSVER: &str = "1.0";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PagosTp {
+  pub versn: String,
+  pub sourc: String,
+  pub pagos: Vec<PagoTp>
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PagoTp {
+  pub versn: String,
+  pub cmpny: String,
+  pub custo: String,
+  pub docnm: String,
+  pub fecha: String,
+  pub forma: String,
+  pub moned: String,
+  pub tcamb: Decimal,
+  pub monto: Decimal,
+  pub numop: String,
+  pub rfcor: String,
+  pub bnkor: String,
+  pub ctaor: String,
+  pub rfcbf: String,
+  pub ctabf: String,
+  pub totls: TotalesTp,
+  pub impts: Vec<TaxlineTp>,
+  pub doctos: Vec<DoctoTp>
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TotalesTp {
+  pub retiv: Decimal,
+  pub retis: Decimal,
+  pub retie: Decimal,
+  pub tb16 : Decimal,
+  pub ti16 : Decimal,
+  pub tb08 : Decimal,
+  pub ti08 : Decimal,
+  pub tb00 : Decimal,
+  pub ti00 : Decimal,
+  pub tbexe: Decimal,
+  pub monto: Decimal
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DoctoTp {
+  pub iddoc: String,
+  pub docnm: String,
+  pub moned: String,
+  pub equiv: Decimal,
+  pub parcl: String,
+  pub salda: Decimal,
+  pub pagad: Decimal,
+  pub salin: Decimal,
+  pub objim: String,
+  pub taxcd: String,
+  pub impts: Vec<TaxlineTp>
+}
+
+// Takes the payment in process (payment line, its DRs and tax groups) out of
+// the work buffers
+pub fn get_pago(p: &Pagos2Tp) -> PagoTp {
+  let decmx = p.c.cur_decim(MXN, p.s.DECPS);
+  let decpy = p.c.cur_decim(&p.lps[CURCY], p.s.DECPS);
+  let (lps, lpf) = (&p.lps, &p.lpf);
+  let mut pago = PagoTp {
+    versn: JSVER.to_string(),
+    cmpny: lps[CMPNY].clone(),
+    custo: lps[CUSTO].clone(),
+    docnm: lps[DOCNM].clone(),
+    fecha: xl_datetime(&lps[PYDTE]),
+    forma: fmt_code(&lps[PYFRM]),
+    moned: lps[CURCY].clone(),
+    tcamb: js_round(lpf[EXCHG], RTDEC),
+    monto: js_round(lpf[AMOUN], decpy),
+    numop: lps[NUMOP].clone(),
+    rfcor: lps[RFCOR].clone(),
+    bnkor: lps[BNKOR].clone(),
+    ctaor: lps[CTAOR].clone(),
+    rfcbf: lps[RFCBF].clone(),
+    ctabf: lps[CTABF].clone(),
+    totls: TotalesTp {
+      retiv: js_round(lpf[TRETN], decmx),
+      retis: js_round(lpf[TRISR], decmx),
+      retie: js_round(lpf[TRIEP], decmx),
+      tb16 : js_round(lpf[TTB16], decmx),
+      ti16 : js_round(lpf[TTA16], decmx),
+      tb08 : js_round(lpf[TTB08], decmx),
+      ti08 : js_round(lpf[TTA08], decmx),
+      tb00 : js_round(lpf[TTB00], decmx),
+      ti00 : js_round(lpf[TTA00], decmx),
+      tbexe: js_round(lpf[TTBEX], decmx),
+      monto: js_round(lpf[TPAYM], decmx)
+    },
+    impts: rnd_taxes(&p.ptx, decpy),
+    doctos: Vec::new()
+  };
+  for i in 0..p.ipp {
+    let (lis, lif) = (&p.gis[i], &p.gif[i]);
+    let decdr = p.c.cur_decim(&lis[CURCY], p.s.DECPS);
+    pago.doctos.push(DoctoTp {
+      iddoc: lis[ASGMT].trim().to_string(),
+      docnm: lis[DOCNM].clone(),
+      moned: lis[CURCY].clone(),
+      equiv: lif[IEQUI],
+      parcl: lis[PARTL].trim().to_string(),
+      salda: js_round(lif[PRVAM], decdr),
+      pagad: js_round(lif[PYAMT], decdr),
+      salin: js_round(lif[CURAM], decdr),
+      objim: lis[IOBJI].clone(),
+      taxcd: lis[TAXCD].clone(),
+      impts: rnd_taxes(&p.gtx[i], decdr)
+    });
+  }
+  pago
+}
+
+fn rnd_taxes(ltx: &LineTaxes, decps: u32) -> Vec<TaxlineTp> {
+  ltx.iter().map(|tx| TaxlineTp {
+    tasac: js_round(tx.tasac, RTDEC),
+    base : js_round(tx.base,  decps),
+    impte: js_round(tx.impte, decps),
+    ..tx.clone()
+  }).collect()
+}
+
+// Rounds and keeps decimal positions, so 1000 in MXN goes as "1000.00"
+fn js_round(x: Decimal, decps: u32) -> Decimal {
+  let mut r = rb_round(x, decps);
+  r.rescale(decps);
+  r
+}
+
+// Writes payments of one file as JSON document, returns the file name
+pub fn write_json(p: &Pagos2Tp) -> String {
+  let flnam = format!("{}{}.json", p.s.outdr, p.ostem);
+  let pagos = PagosTp { versn: JSVER.to_string(), sourc: p.flnam.clone(),
+    pagos: p.pagos.clone() };
+  let jf = File::create(&flnam).expect("creation failed");
+  serde_json::to_writer_pretty(jf, &pagos).expect("write failed");
+  flnam
+}
+
+// Writes payments of one file as JSON Lines, returns the file name
+pub fn write_jsonl(p: &Pagos2Tp) -> String {
+  let flnam = format!("{}{}.jsonl", p.s.outdr, p.ostem);
+  let mut jf = File::create(&flnam).expect("creation failed");
+  for pago in &p.pagos {
+    let line = serde_json::to_string(pago).expect("serialization failed");
+    jf.write_all(format!("{}\n", line).as_bytes()).expect("write failed");
+  }
+  flnam
+}
@@ -0,0 +1,73 @@
+// layout.rs: Record layout of .lot output file (fields, delimiter, line end) --
+// [20220406-BAR8TL]
+#![allow(unused)]
+
+use crate::fixvalues::*;
+use crate::rblib::*;
+use crate::settings::LayoutTp;
+
+// Layout resolved against output columns: positions of the fields written for
+// each record type, in the order given. Empty list means all columns
+#[derive(Debug, Clone, Default)]
+pub struct RcdlayTp {
+  pub title: Vec<usize>,       // Columns of title record
+  pub paymt: Vec<usize>,       // Columns of payment record
+  pub invoi: Vec<usize>,       // Columns of DR record
+  pub delim: String,           // Fields delimiter
+  pub trail: bool,             // Delimiter also after last field
+  pub lnend: String            // Line ending
+}
+
+impl RcdlayTp {
+  pub fn new_rcdlay() -> RcdlayTp {
+    RcdlayTp { delim: ODELM.to_string(), trail: true,
+      lnend: CRLF.to_string(), ..Default::default() }
+  }
+
+  // Resolves layout lay over the output column titles ttls. Fields are given
+  // by their title, ignoring case and accents
+  pub fn get_rcdlay(&mut self, lay: &LayoutTp, ttls: &[String]) {
+    let ntitl: Vec<String> = ttls.iter().map(|t| norm_title(t)).collect();
+    let cols = |flds: &Vec<String>| -> Vec<usize> {
+      flds.iter().map(|f| ntitl.iter().position(|t| *t == norm_title(f))
+        .unwrap_or_else(|| panic!("Error: Field '{}' of layout {} not in \
+        output columns", f, lay.name))).collect()
+    };
+    self.title = cols(&lay.title);
+    self.paymt = cols(&lay.paymt);
+    self.invoi = cols(&lay.invoi);
+    if !lay.delim.is_empty() {
+      self.delim = lay.delim.clone();
+    }
+    if !lay.trail.is_empty() {
+      self.trail = lay.trail == "yes";
+    }
+    self.lnend = match lay.lnend.to_uppercase().as_str() {
+      "" | "CRLF" => CRLF.to_string(),
+      "LF"        => LF.to_string(),
+      "CR"        => CR.to_string(),
+                _ => panic!("Error: Line ending of layout {} must be CRLF, LF \
+                  or CR", lay.name),
+    };
+  }
+
+  // Builds one record of type dtype out of the cells of all output columns
+  pub fn fmt_record(&self, dtype: &str, cells: &[String]) -> String {
+    let cols = match dtype {
+      TITLE => &self.title,
+      PAYMT => &self.paymt,
+          _ => &self.invoi,
+    };
+    let flds: Vec<&str> = if cols.is_empty() {
+      cells.iter().map(|c| c.as_str()).collect()
+    } else {
+      cols.iter().map(|i| cells.get(*i).map(|c| c.as_str()).unwrap_or(""))
+        .collect()
+    };
+    let mut rcd = flds.join(&self.delim);
+    if self.trail {
+      rcd.push_str(&self.delim);
+    }
+    rcd
+  }
+}
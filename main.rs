@@ -2,11 +2,21 @@
 // [20220406-BAR8TL]
 #![allow(unused)]
 
+mod batch;
+mod catalogs;
+mod cfdixml;
 mod fixvalues;
+mod inputs;
+mod jsonout;
+mod layout;
 mod pagos2;
 mod rblib;
+mod rounding;
 mod settings;
+mod validate;
+mod xlsxout;
 
+use crate::batch::BatchTp;
 use crate::fixvalues::*;
 use crate::pagos2::Pagos2Tp;
 use crate::rblib::*;
@@ -32,38 +42,26 @@ fn main() {
 // Calculation of taxes for new additional columns in Pagos2 file --------------
 fn tax_calc(parm: settings::ParameTp, mut stg: SettingsTp) {
   stg.set_runvars(parm);
-  let mut p = Pagos2Tp::new_pagos2();
-  // For batch process: browse inputs directory
+  // For batch process: browse inputs directories
   if stg.modep == BATCH {
-    for entry in fs::read_dir(&stg.inpdr).unwrap() {
-      let s = stg.clone();
-      let entry = entry.unwrap().path();
-      if entry.is_dir() {
-        continue;
-      }
-      let filid = Path::new(&entry).file_name().unwrap();
-      let filnm = Path::new(&filid).file_stem().unwrap();
-      let extsn = Path::new(&filid).extension().unwrap();
-      let flide = filid.to_str().unwrap();
-      let flnam = filnm.to_str().unwrap();
-      let flext = extsn.to_str().unwrap();
-      if s.ifilt.len() == 0 || (s.ifilt.len() > 0 &&
-        pass_filter(&s.ifilt, flnam) && extsn == "xlsx") {
-        p.proc_indiv_file(s, flnam, flext);
-      }
-    }
+    let mut b = BatchTp::new_batch();
+    b.list_files(&stg);
+    b.proc_files(stg.wrkrs);
+    b.print_summary();
   // For individual process: use specified file
   } else {
-    let s = stg.clone();
+    let mut s = stg.clone();
+    s.seqnr = 1;
     let filid = stg.inpfl.clone();
     let filnm = Path::new(&filid).file_stem().expect("File not found");
     let extsn = Path::new(&filid).extension().unwrap();
     let flide = filid.as_str();
     let flnam = filnm.to_str().unwrap();
     let flext = extsn.to_str().unwrap();
-    if s.ifilt.len() == 0 || (s.ifilt.len() > 0 &&
-      pass_filter(&s.ifilt, flnam) && extsn == "xlsx") {
-      p.proc_indiv_file(s, flnam, flext);
-    }
+    // File named in the run is taken whatever its extension or the inputs
+    // filter (which only selects files in batch runs), its format is detected
+    // from content when extension is not a known one
+    let mut p = Pagos2Tp::new_pagos2();
+    p.proc_indiv_file(s, flnam, flext);
   }
 }
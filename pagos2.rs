@@ -4,42 +4,115 @@
 #![allow(non_snake_case)]
 #![allow(non_camel_case_types)]
 
+use crate::catalogs::CatalogsTp;
+use crate::cfdixml::CfdixmlTp;
 use crate::fixvalues::{FixvaluesTp, *};
+use crate::inputs::{InputRows, new_rowsource};
+use crate::jsonout::*;
+use crate::layout::RcdlayTp;
 use crate::rblib::*;
+use crate::rounding::*;
 use crate::settings::SettingsTp;
+use crate::validate::val_payment;
+use crate::xlsxout::XlsxoutTp;
 use calamine::DataType;
-use calamine::{Reader, Xlsx, open_workbook};
 use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
 use std::num;
+use std::fs;
 use std::fs::File;
 use std::io::Write;
+use std::path::Path;
 
-type LineInvoStr = Vec<String>;
-type LineInvoNum = Vec<f32>;
+pub type LineInvoStr = Vec<String>;
+pub type LineInvoNum = Vec<Decimal>;
+pub type LineTaxes   = Vec<TaxlineTp>;
+
+// One tax of a DR (amounts in invoice currency) or one payment-level tax group
+// of equal impuesto, factor and rate (amounts in payment currency)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TaxlineTp {
+  pub clase: String,           // T = traslado, R = retencion
+  pub impto: String,           // Impuesto
+  pub tipof: String,           // TipoFactor
+  pub tasac: Decimal,          // TasaOCuota
+  pub base : Decimal,          // Base
+  pub impte: Decimal           // Importe
+}
+
+// Result of processing one input file, for the end-of-run summary
+#[derive(Debug, Clone, Default)]
+pub struct FilesumTp {
+  pub inppt: String,           // Input file path
+  pub stats: String,           // Status: OK, ABORTED, FAILED
+  pub npays: usize,            // Payments written
+  pub ndocs: usize,            // DRs written
+  pub nwarn: usize,            // Warnings
+  pub nerrs: usize,            // Errors
+  pub messg: String,           // Failure reason
+  pub cnsol: Vec<String>       // Console messages of the file
+}
+
+// Validation error found in one input line
+#[derive(Debug, Clone, Default)]
+pub struct ValerrTp {
+  pub rownr: usize,            // Excel row number
+  pub cmpny: String,           // Company code
+  pub docnm: String,           // Document number
+  pub errcd: String,           // Error code (UNKDT, UNKTX, TOLER, NOEXR)
+  pub messg: String,           // Error description
+  pub warng: bool              // Warning only, on-error policy not applied
+}
 
 #[derive(Debug, Clone, Default)]
 pub struct Pagos2Tp {
   pub s    : SettingsTp,       // Program and Run Settings
   pub k    : FixvaluesTp,      // Hardcode
   pub t    : ItablesTp,        // Internal tables
+  pub c    : CatalogsTp,       // SAT catalogs
+  pub l    : RcdlayTp,         // Output record layout
   pub lxs  : [String; 28],     // Input excel line alpha values (array)
-  pub lxf  : [f32; 28],        // Input excel line num values   (array)
+  pub lxf  : [Decimal; 28],    // Input excel line num values   (array)
+  pub lxqty: Decimal,          // Input excel line units paid (Cuota taxes)
   pub lis  : LineInvoStr,      // Invoice single line alpha values
   pub lif  : LineInvoNum,      // Invoice single line num values (invo curr)
   pub ljf  : LineInvoNum,      // Invoice single line num values (paym curr)
   pub cif  : LineInvoNum,      // Invoice cumulative amounts (invo curr)
   pub cjf  : LineInvoNum,      // Invoice cumulative amounts (paym curr)
   pub lps  : Vec<String>,      // Payment single line alpha values
-  pub lpf  : Vec<f32>,         // Payment single line num values
+  pub lpf  : Vec<Decimal>,     // Payment single line num values
   pub gis  : Vec<LineInvoStr>, // Invoice lines in paymnt alpha values
   pub gif  : Vec<LineInvoNum>, // Invoice lines in paymnt num values (invo curr)
   pub gjf  : Vec<LineInvoNum>, // Invoice lines in paymnt num values (paym curr)
-  pub fiv  : [bool; 7],        // First invoice flags           (array)
+  pub ltx  : LineTaxes,        // Invoice single line taxes (invo curr)
+  pub gtx  : Vec<LineTaxes>,   // Invoice lines in paymnt taxes (invo curr)
+  pub ptx  : LineTaxes,        // Payment tax groups (paym curr)
   pub ipp  : usize,            // Index for invoices within a payments
   pub fline: bool,             // First line in input file
   pub recn : i32,              // Input file records number
   pub doctp: String,           // Document type
   pub oline: String,           // Output line
+  pub rownr: usize,            // Current Excel row number
+  pub prwnr: usize,            // Excel row number of current payment
+  pub errs : Vec<ValerrTp>,    // Validation errors found in input file
+  pub skppy: bool,             // Skip current payment (SKIP policy)
+  pub skprw: bool,             // Drop rows up to next payment (SKIP policy)
+  pub abort: bool,             // Abort current file (ABORT policy)
+  pub flnam: String,           // Input file name (stem)
+  pub ostem: String,           // Output files name (stem), as of outnm
+  pub outfs: Vec<String>,      // Additional output files written
+  pub lotcr: bool,             // Output .lot created (outputs under ostem)
+  pub hldms: bool,             // Hold console messages (parallel batch)
+  pub cnsol: Vec<String>,      // Console messages held
+  pub cmap : Vec<Option<usize>>, // Field of each input column (title based)
+  pub qtcol: Option<usize>,    // Input column of units paid, if any
+  pub rcell: Vec<String>,      // Cells of output line being built
+  pub rcrds: Vec<(String, Vec<String>)>, // Output lines (type, cells) for xlsx
+  pub pagos: Vec<PagoTp>,      // Payments built, for json/jsonl
+  pub npays: usize,            // Payments written
+  pub ndocs: usize,            // DRs written
+  pub faild: String,           // Reason the file could not be processed
 }
 
 impl Pagos2Tp {
@@ -52,152 +125,637 @@ impl Pagos2Tp {
     self.s = stg.clone();
     self.k = FixvaluesTp::new_fixvalues();
     self.k.set_fixvalues();
+    self.c = CatalogsTp::new_catalogs();
+    self.c.get_catalogs(&self.s.catlg);
+    let errs = self.c.check_konst(&self.s.IMPTO, &self.s.TIPOF, &self.s.OBJIM);
+    if !errs.is_empty() {
+      panic!("Error: Default codes not valid\n{}", errs.join("\n"));
+    }
     self.t = ItablesTp::new_itables();
-    self.t.get_itables();
+    self.t.get_itables(&self.s.itabl, &self.c);
+    self.l = RcdlayTp::new_rcdlay();
+    if let Some(lay) = self.s.seek_layout() {
+      let ttls = self.out_titles();
+      self.l.get_rcdlay(lay, &ttls);
+    }
+    self.flnam = fnm.to_string();
     self.allocate_storage(); // allocate memory for work vectors
-    let mut OF = File::create(format!("{}{}.lot", self.s.outdr, fnm))
-      .expect("creation failed");
-    let mut i: usize = 0;
-    let mut excel: Xlsx<_> = open_workbook(&self.s.inppt).unwrap();
-    if let Some(Ok(r)) = excel.worksheet_range(TAB) {
-      for row in r.rows() {
-        let s = stg.clone();
+    let shets = match new_rowsource(&self.s).read_rows() {
+      Ok(shets) => shets,
+      Err(e)   => {
+        self.print_msg(format!("Error: {}", e));
+        self.faild = e;
+        return;
+      },
+    };
+    let mut hdrow = false; // first row is the title, when recognised
+    let rows = shets.first().cloned().unwrap_or_default();
+    if !rows.is_empty() {
+      self.rownr = rows[0].0;
+      hdrow = self.map_columns(&rows[0].1);
+    }
+    let cmpny = self.first_cmpny(&rows, hdrow);
+    // Outputs are created under their final name from template outnm. This
+    // replaces the former rename of the output to out_<input>.lot when renam
+    // is yes (template 'out_'+inpfl gives that name); renam now only applies
+    // to the input, named from template inpnm
+    self.ostem = fmt_name(&self.s.outnm, &self.s.name_tokens(fnm, &cmpny))
+      .unwrap_or_else(|e| panic!("Error: {}", e));
+    let outpt = format!("{}{}.lot", self.s.outdr, self.ostem);
+    self.guard_input(&outpt);
+    if self.out_format(XLSX) {
+      self.guard_input(&format!("{}{}.xlsx", self.s.outdr, self.ostem));
+    }
+    let mut OF = File::create(&outpt).expect("creation failed");
+    self.lotcr = true;
+    if !hdrow {
+      self.print_title(&mut OF);
+    }
+    let mut i: usize = if hdrow { 0 } else { 1 }; // line 0 is the title
+    for (n, rows) in shets.iter().enumerate() {
+      // Each further worksheet has its own columns: they are mapped from its
+      // title row, if any, which is not taken as data
+      let mut skip = 0;
+      if n > 0 && !rows.is_empty() {
+        self.rownr = rows[0].0;
+        skip = if self.map_columns(&rows[0].1) { 1 } else { 0 };
+      }
+      for (rownr, row) in rows.iter().skip(skip) {
+        if self.abort {
+          break;
+        }
+        self.rownr = *rownr;
         self.get_linefields(row, i);
-        println!("{:?} {:?}", self.lxs, self.lxf);
         self.doctp = self.t.seek_doctp(&self.lxs[CMPNY], &self.lxs[DOCTP]);
         match self.doctp.as_str() {
           TITLE => { self.print_title(&mut OF);   },
           PAYMT => { self.proc_paymline(&mut OF); },
           INVOI => { self.proc_involine();        },
-              _ => {},
+              _ => { self.proc_unkdline();        },
         };
         i += 1;
         self.lxs = Default::default();
         self.lxf = Default::default();
       }
+    }
+    if !self.abort && !self.fline {
       self.build_paymline(&mut OF).build_involines(&mut OF);
-      if self.s.renam == "yes" {
-        ren_file("inp", self.s.inpdr.clone(), fnm, fex);
-        ren_file("out", self.s.outdr.clone(), fnm, fex);
+    }
+    if self.abort {
+      self.print_errors();
+      self.write_errors();
+      drop(OF);
+      fs::remove_file(&outpt).expect("removal failed");
+      for outfl in &self.outfs {
+        fs::remove_file(outfl).expect("removal failed");
       }
+      self.print_msg(format!("File {} aborted, no output written", fnm));
+      return;
+    }
+    if self.out_format(XLSX) {
+      let outfl = XlsxoutTp::new_xlsxout().write_xlsx(self);
+      self.outfs.push(outfl);
+    }
+    // No JSON output for a file without payments written
+    if self.out_format(JSON) && !self.pagos.is_empty() {
+      let outfl = write_json(self);
+      self.outfs.push(outfl);
+    }
+    if self.out_format(JSONL) && !self.pagos.is_empty() {
+      let outfl = write_jsonl(self);
+      self.outfs.push(outfl);
+    }
+    self.print_errors();
+    self.write_errors();
+    if self.s.renam == "yes" {
+      let inpnm = fmt_name(&self.s.inpnm, &self.s.name_tokens(fnm, &cmpny))
+        .unwrap_or_else(|e| panic!("Error: {}", e));
+      let inpdr = Path::new(&self.s.inppt).parent()
+        .map(|d| d.to_path_buf()).unwrap_or_default();
+      ren_file(&self.s.inppt, inpdr.join(format!("{}.{}", inpnm, fex))
+        .to_str().unwrap());
+    }
+  }
+
+  // Stops before any output is written when output file outpt is the input
+  // file itself (same name in the same folder)
+  fn guard_input(&self, outpt: &str) {
+    let inpcn = fs::canonicalize(&self.s.inppt).ok();
+    if inpcn.is_some() && fs::canonicalize(outpt).ok() == inpcn {
+      panic!("Error: Output file {} would overwrite input file", outpt);
+    }
+  }
+
+  // Removes outputs of the file written so far, after it failed half way.
+  // Nothing is removed until the .lot is created, as up to then names under
+  // ostem may still be taken by the input or other files
+  pub fn remove_outputs(&self) {
+    if !self.lotcr {
+      return;
+    }
+    let ostem = format!("{}{}", self.s.outdr, self.ostem);
+    let errsf = format!("{}.txt", ERRSF);
+    for sufix in [".lot", &errsf, ".xlsx", ".json", ".jsonl"] {
+      let _ = fs::remove_file(format!("{}{}", ostem, sufix));
+    }
+    for outfl in &self.outfs {
+      let _ = fs::remove_file(outfl);
+    }
+  }
+
+  // Summary of the file processed: status and counts of payments, DRs and
+  // findings
+  pub fn get_filesum(&self) -> FilesumTp {
+    let nwarn = self.errs.iter()
+      .filter(|e| e.warng || self.s.onerr == WARNG).count();
+    FilesumTp {
+      inppt: self.s.inppt.clone(),
+      stats: if !self.faild.is_empty() { FAILD.to_string() }
+        else if self.abort { ABRTD.to_string() } else { OK.to_string() },
+      npays: if self.abort { 0 } else { self.npays },
+      ndocs: if self.abort { 0 } else { self.ndocs },
+      nwarn,
+      nerrs: self.errs.len() - nwarn,
+      messg: self.faild.clone(),
+      cnsol: self.cnsol.clone()
     }
   }
 
+  // Company code of the first data row, for naming of output files
+  fn first_cmpny(&self, rows: &InputRows, hdrow: bool) -> String {
+    let col = match self.cmap.iter().position(|f| *f == Some(CMPNY)) {
+      Some(col) => col,
+      None      => return "".to_string(),
+    };
+    let skip = if hdrow { 1 } else { 0 };
+    rows.iter().skip(skip).filter_map(|(_, row)| match row.get(col) {
+      Some(DataType::String(s)) => Some(s.trim().to_string()),
+      Some(DataType::Float(f))  => Some(format!("{}", f)),
+      Some(DataType::Int(i))    => Some(format!("{}", i)),
+      _                         => None,
+    }).find(|c| !c.is_empty()).unwrap_or_default()
+  }
+
   // Logic for Payments
   fn proc_paymline(&mut self, mut OF: &mut File) {
+    self.skprw = false;
     if self.fline {
       self.fline = false;
+      self.skppy = false; // errors before first payment belong to no payment
     } else {
       self.build_paymline(&mut OF).build_involines(&mut OF).reset_paymdata();
     }
     self.store_paym();
   }
 
-  // Logic for Invoices
+  // Logic for lines with document type not in internal tables. Under SKIP
+  // policy the payment in process is dropped whole, as its DRs can no longer
+  // add up to its amount, and so are rows up to next payment
+  fn proc_unkdline(&mut self) {
+    if self.lxs[CMPNY].is_empty() && self.lxs[DOCTP].is_empty() {
+      return; // blank line
+    }
+    let messg = format!("Document type '{}' not found", self.lxs[DOCTP]);
+    self.add_error(UNKDT, messg);
+    if self.s.onerr == SKIPP {
+      self.skprw = true;
+    }
+  }
+
+  // Logic for Invoices. Consecutive lines of the same document (one per tax
+  // code) are grouped into one DR carrying the taxes of all of them
   fn proc_involine(&mut self) {
-    let txdta = self.t.seek_taxcd(&self.lxs[CMPNY], &self.lxs[TAXCD]);
-    let trate: f32 = txdta.trate;
-    let wrate: f32 = txdta.wrate;
-    let amtic: f32 = self.lxf[PYAMT];
-    let mut convf: f32 = self.lxf[EXCHG];;
-    if self.lps[CURCY] == MXN && self.lxs[CURCY] == MXN {
-      convf = 1.0;
-    }
-    self.lis[IOBJI]  = self.s.OBJIM.clone();
-    self.lif[ITBAS]  = amtic / (1.0 + trate - wrate);
-    self.ljf[ITBAS]  = amtic / (1.0 + trate - wrate) * convf;
-    self.lis[ITIMP]  = self.s.IMPTO.clone();
-    self.lis[ITFAC]  = self.s.TIPOF.clone();
-    self.lif[ITRTE]  = trate;
-    self.lif[ITAMT]  = self.lif[ITBAS] * trate;
-    self.ljf[ITAMT]  = self.lif[ITBAS] * trate * convf;
+    if self.skprw {
+      return;
+    }
+    let txdta = match self.t.seek_taxcd(&self.lxs[CMPNY], &self.lxs[TAXCD]) {
+      Some(txdta) => txdta,
+      None        => {
+        let messg = format!("Tax code '{}' not found", self.lxs[TAXCD]);
+        self.add_error(UNKTX, messg);
+        if self.s.onerr != WARNG {
+          return;
+        }
+        Default::default()
+      },
+    };
+    // Invoices not subject to tax breakdown (ObjetoImp other than 02) carry
+    // no taxes and stay out of payment-level tax totals
+    let objim = if !txdta.objim.is_empty()
+      { txdta.objim.clone() } else { self.s.OBJIM.clone() };
+    let taxes = if objim == OBJSI
+      { txdta.get_taxes(&self.s.IMPTO, &self.s.TIPOF) } else { Vec::new() };
+    let amtic: Decimal = self.lxf[PYAMT];
+    let equiv: Decimal = self.calc_equiv();
+    let decdr: u32 = self.c.cur_decim(&self.lxs[CURCY], self.s.DECPS);
+    let decpy: u32 = self.c.cur_decim(&self.lps[CURCY], self.s.DECPS);
+    // Cuota taxes are an amount per unit paid: their base is the quantity of
+    // the line, so it must be given
+    let quant: Decimal = self.lxqty;
+    if quant.is_zero() && taxes.iter().any(|tx| tx.tipof == CUOTA) {
+      let messg = format!("Tax code '{}' has {} taxes and the line no \
+        quantity", self.lxs[TAXCD], CUOTA);
+      self.add_error(NOQTY, messg);
+      if self.s.onerr != WARNG {
+        return;
+      }
+    }
+    // Base is the paid amount net of all traslados and retenciones, Cuota
+    // amounts taken out first. IEPS traslados form part of the base of IVA
+    let iepst: Decimal = taxes.iter()
+      .filter(|tx| tx.clase == TRASL && tx.impto == IEPS && tx.tipof != CUOTA)
+      .map(|tx| tx.tasac).sum();
+    let iepsc: Decimal = taxes.iter()
+      .filter(|tx| tx.clase == TRASL && tx.impto == IEPS && tx.tipof == CUOTA)
+      .map(|tx| rb_round(quant * tx.tasac, decdr)).sum();
+    let mut factr: Decimal = Decimal::ONE;
+    let mut fixed: Decimal = Decimal::ZERO;
+    for tx in &taxes {
+      let (rate, amunt) = if tx.tipof == CUOTA {
+        (Decimal::ZERO, rb_round(quant * tx.tasac, decdr))
+      } else if tx.impto == IVA {
+        (tx.tasac * (Decimal::ONE + iepst), tx.tasac * iepsc)
+      } else {
+        (tx.tasac, Decimal::ZERO)
+      };
+      if tx.clase == TRASL {
+        factr += rate;
+        fixed += amunt;
+      } else {
+        factr -= rate;
+        fixed -= amunt;
+      }
+    }
+    let based: Decimal = rb_round((amtic - fixed) / factr, decdr);
+    let basiv: Decimal = rb_round((amtic - fixed) / factr *
+      (Decimal::ONE + iepst) + iepsc, decdr);
+    self.lis[IOBJI] = objim;
+    self.ltx.clear();
+    // Input states no DR tax amounts: they come rounded from base and rate,
+    // so within SAT tolerance limits by construction
+    for tx in &taxes {
+      let base = if tx.tipof == CUOTA { quant }
+        else if tx.impto == IVA { basiv } else { based };
+      let txl = TaxlineTp {
+        clase: tx.clase.clone(),
+        impto: tx.impto.clone(),
+        tipof: tx.tipof.clone(),
+        tasac: tx.tasac,
+        base,
+        impte: if tx.tipof == EXENT { Decimal::ZERO } else {
+          rb_round(base * tx.tasac, decdr)
+        }
+      };
+      self.add_ptax(&txl, equiv, decpy);
+      self.ltx.push(txl);
+    }
     self.cif[TPAYM] += amtic;
-    self.cjf[TPAYM] += amtic * convf;
-    if trate == 0.16 {
-      self.cif[PTB16] += self.lif[ITBAS];
-      self.cjf[PTB16] += self.ljf[ITBAS];
-      self.cif[PTA16] += self.lif[ITAMT];
-      self.cjf[PTA16] += self.ljf[ITAMT];
-    } else if trate == 0.08 {
-      self.cif[PTB08] += self.lif[ITBAS];
-      self.cjf[PTB08] += self.ljf[ITBAS];
-      self.cif[PTA08] += self.lif[ITAMT];
-      self.cjf[PTA08] += self.ljf[ITAMT];
-    } else if trate == 0.00 {
-      self.cif[PTB00] += self.lif[ITBAS];
-      self.cjf[PTB00] += self.ljf[ITBAS];
-      self.cif[PTA00] += self.lif[ITAMT];
-      self.cjf[PTA00] += self.ljf[ITAMT];
-    }
-    self.lif[IRBAS] = 0.0;
-    self.ljf[IRBAS] = 0.0;
-    self.lis[IRIMP] = "".to_string();
-    self.lis[IRFAC] = "".to_string();
-    self.lif[IRRTE] = 0.0;
-    self.lif[IRAMT] = 0.0;
-    self.ljf[IRAMT] = 0.0;
-    if wrate != 0.00 {
-      self.lif[IRBAS] = self.lif[ITBAS];
-      self.ljf[IRBAS] = self.lif[ITBAS] + convf;
-      self.lis[IRIMP] = self.s.IMPTO.clone();
-      self.lis[IRFAC] = self.s.TIPOF.clone();
-      self.lif[IRRTE] = wrate;
-      self.lif[IRAMT] = self.lif[IRBAS] * wrate;
-      self.ljf[IRAMT] = self.lif[IRBAS] * wrate * convf;
-      if wrate == 0.16 {
-        self.cif[PRB16] += self.lif[IRBAS];
-        self.cjf[PRB16] += self.ljf[IRBAS];
-        self.cif[PRA16] += self.lif[IRAMT];
-        self.cjf[PRA16] += self.ljf[IRAMT];
-      } else if wrate == 0.08 {
-        self.cif[PRB08] += self.lif[IRBAS];
-        self.cjf[PRB08] += self.ljf[IRBAS];
-        self.cif[PRA08] += self.lif[IRAMT];
-        self.cjf[PRA08] += self.ljf[IRAMT];
-      }
-    }
-    self.store_invo();
+    self.cjf[TPAYM] += rb_round(amtic / equiv, decpy);
+    for j in SUMFLDS {
+      self.ljf[j] = rb_round(self.lxf[j] / equiv, decpy);
+    }
+    let merge = self.ipp > 0 &&
+      self.gis[self.ipp - 1][DOCNM] == self.lxs[DOCNM] &&
+      self.gis[self.ipp - 1][CMPNY] == self.lxs[CMPNY];
+    let overp = merge && self.over_drmax(&self.gtx[self.ipp - 1]).is_some();
+    if merge {
+      self.merge_invo(decdr);
+    } else {
+      Self::set_taxcols(&mut self.lis, &mut self.lif, &self.ltx, 0);
+      self.store_invo();
+    }
+    // Too many taxes for COLS layout is a limit of the output, not an input
+    // error: it is reported once per DR and skips only its payment
+    if let Some((ntrsl, nretn)) = self.over_drmax(&self.gtx[self.ipp - 1]) {
+      if !overp {
+        let messg = format!("{} traslados and {} retenciones, {} allowed",
+          ntrsl, nretn, self.s.drmax);
+        self.push_error(TMTAX, messg);
+        if self.s.onerr != WARNG {
+          self.skppy = true;
+        }
+      }
+    }
+  }
+
+  // Counts of traslados and retenciones of a DR when over the maximum of
+  // tax groups in COLS layout
+  fn over_drmax(&self, ltx: &LineTaxes) -> Option<(usize, usize)> {
+    let ntrsl = ltx.iter().filter(|tx| tx.clase == TRASL).count();
+    let nretn = ltx.iter().filter(|tx| tx.clase == RETNC).count();
+    if self.s.drlay == COLS && (ntrsl > self.s.drmax || nretn > self.s.drmax) {
+      Some((ntrsl, nretn))
+    } else {
+      None
+    }
+  }
+
+  // Adds current line into previous DR of the same document: paid amounts,
+  // tax codes and taxes (added up when of same impuesto, factor and rate).
+  // Saldo anterior is the one of the first line, saldo insoluto the one of
+  // the last line. Lines either repeat the balances of the document or chain
+  // them (saldo anterior of a line is insoluto of the previous one), other
+  // balances are reported
+  fn merge_invo(&mut self, decdr: u32) {
+    let i = self.ipp - 1;
+    let (prvam, curam) = (self.gif[i][PRVAM], self.gif[i][CURAM]);
+    if self.lxf[PRVAM] != prvam && self.lxf[PRVAM] != curam {
+      let messg = format!("ImpSaldoAnt {} of line follows neither ImpSaldoAnt \
+        {} nor ImpSaldoInsoluto {} of the document lines before",
+        self.lxf[PRVAM], prvam, curam);
+      self.add_error(SALDO, messg);
+    }
+    self.gif[i][CURAM] = self.lxf[CURAM];
+    self.gis[i][CURAM] = self.lxs[CURAM].clone();
+    for j in SUMFLDS {
+      self.gif[i][j] += self.lxf[j];
+      self.gjf[i][j] += self.ljf[j];
+      self.gis[i][j]  = format!("{:.*}", decdr as usize,
+        rb_round(self.gif[i][j], decdr));
+    }
+    if !self.gis[i][TAXCD].split('/').any(|tc| tc == self.lxs[TAXCD]) {
+      self.gis[i][TAXCD] = format!("{}/{}", self.gis[i][TAXCD],
+        self.lxs[TAXCD]);
+    }
+    if self.lis[IOBJI] == OBJSI {
+      self.gis[i][IOBJI] = self.lis[IOBJI].clone();
+    }
+    for txl in self.ltx.clone() {
+      match self.gtx[i].iter_mut().find(|tx| tx.clase == txl.clase &&
+        tx.impto == txl.impto && tx.tipof == txl.tipof && tx.tasac == txl.tasac) {
+        Some(tx) => { tx.base += txl.base; tx.impte += txl.impte; },
+        None     => { self.gtx[i].push(txl); },
+      };
+    }
+    Self::set_taxcols(&mut self.gis[i], &mut self.gif[i], &self.gtx[i], 0);
+  }
+
+  // Accumulates one DR tax into the payment-level group of same impuesto,
+  // factor and rate, converted to payment currency (a Cuota base is units)
+  fn add_ptax(&mut self, txl: &TaxlineTp, equiv: Decimal, decpy: u32) {
+    let base  = if txl.tipof == CUOTA { txl.base }
+      else { rb_round(txl.base  / equiv, decpy) };
+    let impte = rb_round(txl.impte / equiv, decpy);
+    for g in self.ptx.iter_mut() {
+      if g.clase == txl.clase && g.impto == txl.impto && g.tipof == txl.tipof &&
+        g.tasac == txl.tasac {
+        g.base  += base;
+        g.impte += impte;
+        return;
+      }
+    }
+    self.ptx.push(TaxlineTp { base, impte, ..txl.clone() });
+  }
+
+  // Fills DR tax columns with k-th traslado and k-th retencion of the DR
+  fn set_taxcols(lis: &mut LineInvoStr, lif: &mut LineInvoNum, ltx: &LineTaxes,
+    k: usize) {
+    for i in ITBAS..=IRAMT {
+      lis[i] = "".to_string();
+      lif[i] = Decimal::ZERO;
+    }
+    for (clase, col) in [(TRASL, ITBAS), (RETNC, IRBAS)] {
+      if let Some(tx) = ltx.iter().filter(|tx| tx.clase == clase).nth(k) {
+        lif[col]     = tx.base;
+        lis[col + 1] = tx.impto.clone();
+        lis[col + 2] = tx.tipof.clone();
+        lif[col + 3] = tx.tasac;
+        lif[col + 4] = tx.impte;
+      }
+    }
+  }
+
+  // EquivalenciaDR: units of invoice currency worth one unit of payment
+  // currency, "1" when both currencies are the same. Exchange rates (to MXN)
+  // come from invoice line and payment line
+  fn calc_equiv(&mut self) -> Decimal {
+    if self.lxs[CURCY] == self.lps[CURCY] {
+      self.lis[IEQUI] = "1".to_string();
+      self.lif[IEQUI] = Decimal::ONE;
+      return Decimal::ONE;
+    }
+    let exdr = if self.lxs[CURCY] == MXN
+      { Decimal::ONE } else { self.lxf[EXCHG] };
+    let expy = self.lpf[EXCHG];
+    if exdr.is_zero() || expy.is_zero() {
+      let messg = format!("Exchange rate missing for {} to {} equivalence, \
+        1 assumed", self.lxs[CURCY], self.lps[CURCY]);
+      self.add_warning(INVOI, NOEXR, messg);
+      self.lis[IEQUI] = "1".to_string();
+      self.lif[IEQUI] = Decimal::ONE;
+      return Decimal::ONE;
+    }
+    let equiv = rb_round(expy / exdr, EQDEC).normalize();
+    self.lis[IEQUI] = format!("{}", equiv);
+    self.lif[IEQUI] = equiv;
+    equiv
+  }
+
+  // Records one validation error and applies the on-error policy
+  fn add_error(&mut self, errcd: &str, messg: String) {
+    self.push_error(errcd, messg);
+    match self.s.onerr.as_str() {
+      ABORT => { self.abort = true; },
+      SKIPP => { self.skppy = true; },
+          _ => {},
+    };
+  }
+
+  // Records one validation error of current line, policy left to the caller
+  fn push_error(&mut self, errcd: &str, messg: String) {
+    self.errs.push(ValerrTp {
+      rownr: self.rownr,
+      cmpny: self.lxs[CMPNY].clone(),
+      docnm: self.lxs[DOCNM].clone(),
+      errcd: errcd.to_string(),
+      messg,
+      warng: false
+    });
+  }
+
+  // Error of the payment in process, referred to the payment row
+  fn pay_error(&self, errcd: &str, messg: String) -> ValerrTp {
+    ValerrTp {
+      rownr: self.prwnr,
+      cmpny: self.lps[CMPNY].clone(),
+      docnm: self.lps[DOCNM].clone(),
+      errcd: errcd.to_string(),
+      messg,
+      warng: false
+    }
+  }
+
+  // Records errors of the payment in process and applies the on-error policy
+  // to the payment
+  fn add_payerrs(&mut self, vlerr: Vec<ValerrTp>) {
+    if vlerr.is_empty() {
+      return;
+    }
+    self.errs.extend(vlerr);
+    match self.s.onerr.as_str() {
+      ABORT => { self.abort = true; },
+      SKIPP => { self.skppy = true; },
+          _ => {},
+    };
+  }
+
+  // Records one warning for current payment (PAYMT) or current line, then
+  // processing goes on regardless of on-error policy
+  fn add_warning(&mut self, dtype: &str, errcd: &str, messg: String) {
+    let (rownr, cmpny, docnm) = if dtype == PAYMT {
+      (self.prwnr, self.lps[CMPNY].clone(), self.lps[DOCNM].clone())
+    } else {
+      (self.rownr, self.lxs[CMPNY].clone(), self.lxs[DOCNM].clone())
+    };
+    self.errs.push(ValerrTp {
+      rownr,
+      cmpny,
+      docnm,
+      errcd: errcd.to_string(),
+      messg,
+      warng: true
+    });
+  }
+
+  // Checks one tax amount against SAT tolerance limits of its base and rate,
+  // warning when out of them
+  fn chk_taxamt(&mut self, dtype: &str, what: &str, amunt: Decimal,
+    base: Decimal, rate: Decimal, decim: u32) {
+    let linf = lim_inf(base, rate, decim);
+    let lsup = lim_sup(base, rate, decim);
+    if amunt < linf || amunt > lsup {
+      let messg = format!("{} {} out of tolerance {}..{} for base {} at rate \
+        {}", what, amunt, linf, lsup, base, rate);
+      self.add_warning(dtype, TOLER, messg);
+    }
+  }
+
+  fn print_errors(&mut self) {
+    let lines: Vec<String> = self.errs.iter().map(|e| format!(
+      "{}: Row {}, company {}, document {}: {} - {}",
+      if e.warng || self.s.onerr == WARNG { "Warning" } else { "Error" },
+      e.rownr, e.cmpny, e.docnm, e.errcd, e.messg)).collect();
+    for line in lines {
+      self.print_msg(line);
+    }
+  }
+
+  // Console message of the file: printed at once, or held when files are
+  // processed in parallel, to be printed with the other ones of the file
+  fn print_msg(&mut self, messg: String) {
+    if self.hldms {
+      self.cnsol.push(messg);
+    } else {
+      println!("{}", messg);
+    }
+  }
+
+  // Writes errors and rule findings of the file, one per line, next to output.
+  // No report when nothing was found
+  fn write_errors(&self) {
+    if self.errs.is_empty() {
+      return;
+    }
+    let errpt = format!("{}{}{}.txt", self.s.outdr, self.ostem, ERRSF);
+    let mut EF = File::create(errpt).expect("creation failed");
+    let mut eline = "Row|Company Code|Document Number|Severity|Error Code|\
+      Message|".to_string();
+    for e in &self.errs {
+      eline.push_str(&format!("\r\n{}|{}|{}|{}|{}|{}|", e.rownr, e.cmpny,
+        e.docnm, if e.warng || self.s.onerr == WARNG { "Warning" }
+        else { "Error" }, e.errcd, e.messg));
+    }
+    EF.write_all(eline.as_bytes()).expect("write failed");
+  }
+
+  // Matches the titles of the input (first row) against known field titles
+  // and their aliases, ignoring case and accents. Input columns not matched
+  // are left out; a mandatory field with no column aborts the file under
+  // ABORT policy (otherwise the field goes empty). When
+  // no title is recognised the input has no title row and its columns come in
+  // the fixed EDICOM order; returns if the first row is the title. The
+  // optional quantity column is kept apart, out of the input fields
+  fn map_columns(&mut self, row: &[calamine::DataType]) -> bool {
+    let mut names: Vec<Vec<String>> = Vec::new();
+    for j in 0..=TAXCD + 1 {
+      let title = norm_title(if j <= TAXCD { &self.k.TT[j] } else { QTYTL });
+      let mut fnams = vec![title.clone()];
+      for al in self.s.cfd.alias.iter()
+        .filter(|al| norm_title(&al.title) == title) {
+        fnams.extend(al.names.iter().map(|n| norm_title(n)));
+      }
+      names.push(fnams);
+    }
+    self.cmap.clear();
+    self.qtcol = None;
+    for c in row {
+      let title = norm_title(&cell_text(c));
+      let field = (0..=TAXCD).find(|j| names[*j].contains(&title) &&
+        !self.cmap.contains(&Some(*j)));
+      if field.is_none() && self.qtcol.is_none() &&
+        names[TAXCD + 1].contains(&title) {
+        self.qtcol = Some(self.cmap.len());
+      }
+      self.cmap.push(field);
+    }
+    if self.cmap.iter().all(|f| f.is_none()) && self.qtcol.is_none() {
+      self.cmap = (0..=TAXCD).map(Some).collect();
+      self.qtcol = Some(TAXCD + 1);
+      return false;
+    }
+    let missg: Vec<String> = MANDCOLS.iter()
+      .filter(|j| !self.cmap.contains(&Some(**j)))
+      .map(|j| self.k.TT[*j].clone()).collect();
+    if !missg.is_empty() {
+      let messg = format!("Mandatory columns missing: {}", missg.join(", "));
+      self.push_error(MSCOL, messg);
+      if self.s.onerr == ABORT {
+        self.abort = true;
+      }
+    }
+    true
   }
 
   fn get_linefields (&mut self, row: &[calamine::DataType], k: usize) {
     let mut temp: String = Default::default();
-    for (j, c) in row.iter().enumerate() {
-      match *c {
-        DataType::Empty           => { temp = "".to_string();   },
-        DataType::String  (ref s) => { temp =  s.to_string();   },
-        DataType::Float   (ref f) |
-        DataType::DateTime(ref f) => { temp = format!("{}", f); },
-        DataType::Int     (ref i) => { temp = format!("{}", i); },
-        DataType::Error   (ref e) => { temp = format!("{}", e); },
-        DataType::Bool    (ref b) => { temp = format!("{}", b); },
+    self.lxqty = match self.qtcol.and_then(|i| row.get(i)) {
+      Some(c) if k > 0 => cell_text(c).trim().parse::<Decimal>()
+        .unwrap_or(Decimal::ZERO),
+      _                => Decimal::ZERO,
+    };
+    for (i, c) in row.iter().enumerate() {
+      let j = match self.cmap.get(i) {
+        Some(Some(j)) => *j,
+        _             => continue,
       };
+      temp = cell_text(c);
       if k == 0 {
-        self.lxs[j] = temp.clone(); // first row in excel should be the title
+        self.lxs[j] = self.k.TT[j].clone(); // first row should be the title
       } else {
         if contains(&self.k.NUMER, &j) {
-          match temp.parse::<f32>() {
+          match temp.trim().parse::<Decimal>() {
             Ok(rslt)   => {
               self.lxf[j] = rslt;
               self.lxs[j] = format!("{:.2}", rb_round(rslt, 2));
             },
             Err(error) => {
-              self.lxf[j] = 0.0;
+              self.lxf[j] = Decimal::ZERO;
               self.lxs[j] = "0.00".to_string();
             }
           }
         } else if j == EXCHG {
-          match temp.parse::<f32>() {
+          match temp.trim().parse::<Decimal>() {
             Ok(rslt) => {
               self.lxf[j] = rslt;
               self.lxs[j] = format!("{:.6}", rb_round(rslt, 6));
             },
             Err(error) => {
-              if temp == "" || temp == MXN {
-                self.lxf[j] = 1.0;
+              if temp == MXN {
+                self.lxf[j] = Decimal::ONE;
               }
             }
           }
-          if self.lxf[j] == 0.0 {
+          if self.lxf[j].is_zero() {
             self.lxs[j] = "".to_string();
           }
         } else {
@@ -205,226 +763,569 @@ impl Pagos2Tp {
         }
       }
     }
+    // Blank exchange rate stands for 1 only in MXN lines. Other currencies
+    // keep it zero, to be reported as missing
+    let curcy = self.lxs[CURCY].trim().to_string();
+    if k > 0 && curcy == MXN && self.lxf[EXCHG].is_zero() {
+      self.lxf[EXCHG] = Decimal::ONE;
+    }
+    // Currency of the line must be in c_Moneda, as its decimals are used for
+    // rounding
+    if k > 0 && !curcy.is_empty() && self.c.seek_moneda(&curcy).is_none() {
+      let messg = format!("Currency {} not in c_Moneda, {} decimals assumed",
+        curcy, self.s.DECPS);
+      self.add_warning(INVOI, UNKCY, messg);
+    }
     if self.doctp.as_str() == PAYMT {
-      if self.lxf[PYAMT] == 0.0 {
+      if self.lxf[PYAMT].is_zero() {
         self.lxs[PYAMT] = "".to_string();
       }
-      if self.lxf[PRVAM] == 0.0 {
+      if self.lxf[PRVAM].is_zero() {
         self.lxs[PRVAM] = "".to_string();
       }
-      if self.lxf[CURAM] == 0.0 {
+      if self.lxf[CURAM].is_zero() {
         self.lxs[CURAM] = "".to_string();
       }
     }
   }
 
   fn store_paym(&mut self) {
+    self.prwnr = self.rownr;
     for i in 0..28 {
       self.lps[i] = self.lxs[i].clone();
-      self.lpf[i] = self.lxf[i].clone();
+      self.lpf[i] = self.lxf[i];
     }
     if self.lxs[CURCY] == MXN {
-      self.lpf[EXCHG] = 1.0;
+      self.lpf[EXCHG] = Decimal::ONE;
     } else {
       self.lpf[EXCHG] = self.lxf[EXCHG];
+      if self.lpf[EXCHG].is_zero() {
+        let messg = format!("Exchange rate missing for payment in {}, MXN \
+          totals not computed", self.lxs[CURCY]);
+        self.add_warning(PAYMT, NOEXR, messg);
+      }
     }
   }
 
   fn store_invo(&mut self) {
     for i in 0..28 {
       self.lis[i] = self.lxs[i].clone();
-      self.lif[i] = self.lxf[i].clone();
+      self.lif[i] = self.lxf[i];
     }
     if self.ipp < self.gis.len() {
       self.gis[self.ipp] = self.lis.clone();
       self.gif[self.ipp] = self.lif.clone();
       self.gjf[self.ipp] = self.ljf.clone();
+      self.gtx[self.ipp] = self.ltx.clone();
     } else {
       self.gis.push(self.lis.clone());
       self.gif.push(self.lif.clone());
       self.gjf.push(self.ljf.clone());
+      self.gtx.push(self.ltx.clone());
     }
     self.ipp += 1;
-    // Reset cumulative amounts of Multiple-taxcode payments
-    if self.lif[ITRTE] == 0.16 {
-      if self.fiv[FIT16] {
-        self.lps[PTI16] = self.lis[ITIMP].clone();
-        self.lps[PTF16] = self.lis[ITFAC].clone();
-        self.lpf[PTR16] = self.lif[ITRTE].clone();
-        self.fiv[FIT16] = false;
-      }
-    }
-    if self.lif[IRRTE] == 0.16 {
-      if self.fiv[FIR16] {
-        self.lps[PRI16] = self.lis[IRIMP].clone();
-        self.lps[PRF16] = self.lis[IRFAC].clone();
-        self.lpf[PRR16] = self.lif[IRRTE].clone();
-        self.fiv[FIR16] = false;
-      }
-    }
-    if self.lif[ITRTE] == 0.08 {
-      if self.fiv[FIT08] {
-        self.lps[PTI08] = self.lis[ITIMP].clone();
-        self.lps[PTF08] = self.lis[ITFAC].clone();
-        self.lpf[PTR08] = self.lif[ITRTE].clone();
-        self.fiv[FIT08] = false;
-      }
-    }
-    if self.lif[IRRTE] == 0.08 {
-      if self.fiv[FIR08] {
-        self.lps[PRI08] = self.lis[IRIMP].clone();
-        self.lps[PRF08] = self.lis[IRFAC].clone();
-        self.lpf[PRR08] = self.lif[IRRTE].clone();
-        self.fiv[FIR08] = false;
-      }
-    }
-    if self.lif[ITRTE] == 0.0 {
-      if self.fiv[FIT00] {
-        self.lps[PTI00] = self.lis[ITIMP].clone();
-        self.lps[PTF00] = self.lis[ITFAC].clone();
-        self.lpf[PTR00] = self.lif[ITRTE].clone();
-        self.fiv[FIT00] = false;
-      }
-    }
-    if self.lif[IRRTE] == 0.0 {
-      if self.fiv[FIR00] {
-        self.lps[PRI00] = self.lis[IRIMP].clone();
-        self.lps[PRF00] = self.lis[IRFAC].clone();
-        self.lpf[PRR00] = self.lif[IRRTE].clone();
-        self.fiv[FIR00] = false;
-      }
-    }
   }
 
   fn reset_paymdata(&mut self) -> &mut Pagos2Tp {
     self.ipp = 0;
-    for i in 0..self.fiv.len()-1 {
-      self.fiv[i] = true;
-    }
+    self.skppy = false;
     for i in 0..self.gis.len() {
       self.gis[i] = Default::default();
       self.gif[i] = Default::default();
       self.gjf[i] = Default::default();
+      self.gtx[i] = Default::default();
     }
-    for i in 0..=79 {
+    self.ptx.clear();
+    for i in 0..NFLDS {
       self.lps[i] = "".to_string();
-      self.lpf[i] = 0.0;
+      self.lpf[i] = Decimal::ZERO;
       self.lis[i] = "".to_string();
-      self.lif[i] = 0.0;
-      self.ljf[i] = 0.0;
-      self.cif[i] = 0.0;
-      self.cjf[i] = 0.0;
+      self.lif[i] = Decimal::ZERO;
+      self.ljf[i] = Decimal::ZERO;
+      self.cif[i] = Decimal::ZERO;
+      self.cjf[i] = Decimal::ZERO;
     }
     self
   }
 
   fn build_paymline(&mut self, mut OF: &mut File) -> &mut Pagos2Tp {
-    self.lpf[TRETN] = (self.cjf[PRA16]
-                     + self.cjf[PRA08]
-                     + self.cjf[PRA00]) * self.lpf[EXCHG];
-    self.lpf[TTB16]  = self.cjf[PTB16]  * self.lpf[EXCHG];
-    self.lpf[TTA16]  = self.cjf[PTA16]  * self.lpf[EXCHG];
-    self.lpf[TTB08]  = self.cjf[PTB08]  * self.lpf[EXCHG];
-    self.lpf[TTA08]  = self.cjf[PTA08]  * self.lpf[EXCHG];
-    self.lpf[TTB00]  = self.cjf[PTB00]  * self.lpf[EXCHG];
-    self.lpf[TTA00]  = self.cjf[PTA00]  * self.lpf[EXCHG];
-    self.lpf[TPAYM]  = self.cjf[TPAYM]  * self.lpf[EXCHG];
-    self.lpf[PTB16]  = self.cjf[PTB16].clone();
-    self.lpf[PTA16]  = self.cjf[PTA16].clone();
-    self.lpf[PRB16]  = self.cjf[PRB16].clone();
-    self.lpf[PRA16]  = self.cjf[PRA16].clone();
-    self.lpf[PTB08]  = self.cjf[PTB08].clone();
-    self.lpf[PTA08]  = self.cjf[PTA08].clone();
-    self.lpf[PRB08]  = self.cjf[PRB08].clone();
-    self.lpf[PRA08]  = self.cjf[PRA08].clone();
-    self.lpf[PTB00]  = self.cjf[PTB00].clone();
-    self.lpf[PTA00]  = self.cjf[PTA00].clone();
-    self.lpf[PRB00]  = self.cjf[PRB00].clone();
-    self.lpf[PRA00]  = self.cjf[PRA00].clone();
+    if self.skppy {
+      self.print_msg(format!("Payment {} skipped", self.lps[DOCNM]));
+      return self;
+    }
+    // Payment-level tax amounts are the sums of their DRs converted, kept as
+    // they are so totals match the DRs: out of tolerance of their bases they
+    // are only reported. Cuota amounts are per unit in DR currency, with no
+    // base in payment currency to check them against
+    let decpy = self.c.cur_decim(&self.lps[CURCY], self.s.DECPS);
+    for g in 0..self.ptx.len() {
+      if self.ptx[g].tipof == EXENT || self.ptx[g].tipof == CUOTA {
+        continue;
+      }
+      let (impte, base, rate) =
+        (self.ptx[g].impte, self.ptx[g].base, self.ptx[g].tasac);
+      self.chk_taxamt(PAYMT, "ImporteP", impte, base, rate, decpy);
+    }
+    self.set_pcols();
+    let decmx = self.c.cur_decim(MXN, self.s.DECPS);
+    self.lpf[TPAYM]  = rb_round(self.cjf[TPAYM]  * self.lpf[EXCHG], decmx);
+    // Differences: recomputed totals of its invoices less payment amount
+    self.lpf[DIFTL]  = self.lpf[TPAYM]
+                     - rb_round(self.lpf[AMOUN]  * self.lpf[EXCHG], decmx);
+    self.lpf[DIFPY]  = rb_round(self.cjf[TPAYM], decpy) - self.lpf[AMOUN];
+    // Payment is validated (and CFDI built) before it is written, so on-error
+    // policy can still leave it out
+    let mut vlerr = val_payment(self);
+    let mut cfdi = CfdixmlTp::new_cfdixml();
+    if self.out_format(XML) {
+      for messg in cfdi.build_cfdixml(self) {
+        vlerr.push(self.pay_error(CFDIA, messg));
+      }
+    }
+    self.add_payerrs(vlerr);
+    if self.abort {
+      return self;
+    }
+    if self.skppy {
+      self.print_msg(format!("Payment {} skipped", self.lps[DOCNM]));
+      return self;
+    }
     self.print_paymline(OF);
+    self.npays += 1;
+    self.ndocs += self.ipp;
+    if self.out_format(XML) {
+      // A payment number already written in the file gets the payment row in
+      // its XML name, not to overwrite the former one
+      let mut xstem = format!("{}_{}", self.ostem, self.lps[DOCNM]);
+      if self.outfs.contains(&format!("{}{}.xml", self.s.outdr, xstem)) {
+        xstem = format!("{}_{}", xstem, self.prwnr);
+        let messg = format!("Payment number {} repeated in file, XML written \
+          as {}.xml", self.lps[DOCNM], xstem);
+        self.add_warning(PAYMT, DUPDN, messg);
+      }
+      let outfl = cfdi.write_cfdixml(self, &xstem);
+      self.outfs.push(outfl);
+    }
+    if self.out_format(JSON) || self.out_format(JSONL) {
+      let pago = get_pago(self);
+      self.pagos.push(pago);
+    }
     self
   }
 
+  // Tells if one additional output format was requested for the run
+  fn out_format(&self, outfm: &str) -> bool {
+    self.s.outfm.split(',').any(|f| f.trim() == outfm)
+  }
+
+  // Fills payment-level IVA columns and totals (MXN) from payment tax groups
+  fn set_pcols(&mut self) {
+    let exchg = self.lpf[EXCHG];
+    let decmx = self.c.cur_decim(MXN, self.s.DECPS);
+    for g in self.ptx.clone() {
+      let bsmxn = rb_round(g.base  * exchg, decmx);
+      let immxn = rb_round(g.impte * exchg, decmx);
+      if g.clase == RETNC {
+        match g.impto.as_str() {
+          ISR  => { self.lpf[TRISR] += immxn; },
+          IVA  => { self.lpf[TRETN] += immxn; },
+          IEPS => { self.lpf[TRIEP] += immxn; },
+             _ => {},
+        };
+      }
+      // One IEPS traslado group per payment fits in .lot columns
+      if g.impto == IEPS && g.clase == TRASL {
+        if self.lps[PTIIE].is_empty() {
+          self.lpf[PTBIE] = g.base;
+          self.lps[PTIIE] = g.impto.clone();
+          self.lps[PTFIE] = g.tipof.clone();
+          self.lpf[PTRIE] = g.tasac;
+          self.lpf[PTAIE] = g.impte;
+        } else {
+          let messg = format!("IEPS traslado {} {} left out of output \
+            columns, only one IEPS rate per payment", g.tipof, g.tasac);
+          self.add_warning(PAYMT, MLIEP, messg);
+        }
+      }
+      if g.impto == IVA && g.tipof == EXENT {
+        self.lpf[TTBEX] += bsmxn;
+        self.lpf[PTBEX] += g.base;
+        self.lps[PTIEX]  = g.impto.clone();
+        self.lps[PTFEX]  = g.tipof.clone();
+      }
+      if g.impto != IVA || g.tipof != TASA {
+        continue;
+      }
+      for (clase, rate, col, ttb, tta) in IVACOLS {
+        if g.clase != clase || g.tasac != rate {
+          continue;
+        }
+        self.lpf[col]     = g.base;
+        self.lps[col + 1] = g.impto.clone();
+        self.lps[col + 2] = g.tipof.clone();
+        self.lpf[col + 3] = g.tasac;
+        self.lpf[col + 4] = g.impte;
+        if ttb > 0 {
+          self.lpf[ttb] = bsmxn;
+          self.lpf[tta] = immxn;
+        }
+      }
+    }
+  }
+
   fn build_involines(&mut self, mut OF: &mut File) -> &mut Pagos2Tp {
+    if self.skppy {
+      return self;
+    }
     for i in 0..self.ipp {
-      self.print_involine(OF, self.gis[i].clone(), self.gif[i].clone());
+      self.print_involine(OF, self.gis[i].clone(), self.gif[i].clone(),
+        self.gtx[i].clone());
     }
     self
   }
 
   fn print_title(&mut self, mut OF: &mut File) {
     self.recn += 1;
-    for i in 0..=79 {
-      self.app_strline(self.k.TT[i].clone());
+    for ttl in self.out_titles() {
+      self.app_strline(ttl);
     }
-    OF.write_all(self.oline.as_bytes()).expect("write failed");
-    self.oline = "\r\n".to_string();
+    self.write_line(OF, TITLE);
+  }
+
+  // Titles of all output columns: fields in index order, then extra DR tax
+  // groups when COLS layout
+  fn out_titles(&self) -> Vec<String> {
+    let mut ttls: Vec<String> = self.k.TT[0..NFLDS].to_vec();
+    if self.s.drlay == COLS {
+      for clase in ["Traslado", "Retencion"] {
+        for k in 2..=self.s.drmax {
+          for fld in ["Base", "Impuesto", "TipoFactor", "TasaOCuota", "Importe"] {
+            ttls.push(format!("DR {} {} {}", clase, fld, k));
+          }
+        }
+      }
+    }
+    ttls
   }
 
   fn print_paymline(&mut self, mut OF: &mut File) {
     self.recn += 1;
-    for i in 0..=79 { // Print original columns
+    let decpy = self.c.cur_decim(&self.lps[CURCY], self.s.DECPS);
+    for i in 0..NFLDS { // Print original columns
       if i <= 27 {
         self.app_strline(self.lps[i].clone());
       }
-      if i >= 28 && i <= 79 {
+      if i >= 28 {
         if contains(&self.k.ALPHA, &i) {
           self.app_strline(self.lps[i].clone());
         } else {
-          self.app_numline(self.lpf[i].clone());
+          let decps = self.num_decim(i, decpy);
+          self.app_numline(self.lpf[i], decps);
         }
       }
     }
-    OF.write_all(self.oline.as_bytes()).expect("write failed");
-    self.oline = "\r\n".to_string();
+    if self.s.drlay == COLS {
+      for k in 1..self.s.drmax {
+        self.app_taxgroup(None, decpy);
+        self.app_taxgroup(None, decpy);
+      }
+    }
+    self.write_line(OF, PAYMT);
   }
 
+  // Prints one DR. Further taxes of the DR go either in continuation rows
+  // (ROWS layout) or in additional column groups (COLS layout)
   fn print_involine(&mut self, mut OF: &mut File, lis: LineInvoStr,
-    lif: LineInvoNum) {
-    self.recn += 1;
-    for i in 0..=79 {
-      if i <= 27 {
-        self.app_strline(lis[i].clone());
+    lif: LineInvoNum, ltx: LineTaxes) {
+    let decdr = self.c.cur_decim(&lis[CURCY], self.s.DECPS);
+    let ntrsl = ltx.iter().filter(|tx| tx.clase == TRASL).count();
+    let nretn = ltx.iter().filter(|tx| tx.clase == RETNC).count();
+    let nrows = if self.s.drlay == ROWS { ntrsl.max(nretn).max(1) } else { 1 };
+    for k in 0..nrows {
+      let mut ls = lis.clone();
+      let mut lf = lif.clone();
+      if k > 0 {
+        for i in 0..NFLDS {
+          if !DRKEYS.contains(&i) {
+            ls[i] = "".to_string();
+            lf[i] = Decimal::ZERO;
+          }
+        }
+        Self::set_taxcols(&mut ls, &mut lf, &ltx, k);
       }
-      if i >= 28 && i <= 79 {
-        if contains(&self.k.ALPHA, &i) {
-          self.app_strline(lis[i].clone());
-        } else {
-          self.app_numline(lif[i].clone());
+      self.recn += 1;
+      for i in 0..NFLDS {
+        if i <= 27 {
+          self.app_strline(ls[i].clone());
+        }
+        if i >= 28 {
+          if contains(&self.k.ALPHA, &i) {
+            self.app_strline(ls[i].clone());
+          } else {
+            let decps = self.num_decim(i, decdr);
+            self.app_numline(lf[i], decps);
+          }
         }
       }
+      if self.s.drlay == COLS {
+        for clase in [TRASL, RETNC] {
+          for k in 1..self.s.drmax {
+            let tx = ltx.iter().filter(|tx| tx.clase == clase).nth(k).cloned();
+            self.app_taxgroup(tx, decdr);
+          }
+        }
+      }
+      self.write_line(OF, INVOI);
     }
+  }
+
+  // Appends columns Base, Impuesto, TipoFactor, TasaOCuota, Importe of a tax
+  fn app_taxgroup(&mut self, tx: Option<TaxlineTp>, decps: u32) {
+    let tx = tx.unwrap_or_default();
+    self.app_numline(tx.base, decps);
+    self.app_strline(tx.impto);
+    self.app_strline(tx.tipof);
+    self.app_numline(tx.tasac, RTDEC);
+    self.app_numline(tx.impte, decps);
+  }
+
+  // Writes output line to .lot file, with fields and delimiters of the output
+  // layout; its cells are kept for the workbook when xlsx output is requested
+  fn write_line(&mut self, OF: &mut File, dtype: &str) {
+    let cells = std::mem::take(&mut self.rcell);
+    self.oline.push_str(&self.l.fmt_record(dtype, &cells));
     OF.write_all(self.oline.as_bytes()).expect("write failed");
-    self.oline = "\r\n".to_string();
+    self.oline = self.l.lnend.clone();
+    if self.out_format(XLSX) {
+      self.rcrds.push((dtype.to_string(), cells));
+    }
   }
 
   fn app_strline(&mut self, val: String) {
-    self.oline.push_str(format!("{}|", &val).as_str());
+    self.rcell.push(val);
   }
 
-  fn app_numline(&mut self, val: f32) {
-    if val == 0.0 {
-      self.oline.push_str(format!("|").as_str());
+  fn app_numline(&mut self, val: Decimal, decps: u32) {
+    if val.is_zero() {
+      self.app_strline("".to_string());
     } else {
-      self.oline.push_str(format!("{:.2}|", rb_round(val, self.s.DECPS))
-        .as_str());
+      self.app_strline(format!("{:.*}", decps as usize, rb_round(val, decps)));
+    }
+  }
+
+  // Decimal positions for numeric new field i: rates go with 6 decimals,
+  // totals with MXN decimals, amounts with decimals of the line currency
+  fn num_decim(&self, i: usize, decln: u32) -> u32 {
+    if contains(&self.k.RATES, &i) {
+      return RTDEC;
+    }
+    if contains(&self.k.TOTAL, &i) {
+      return self.c.cur_decim(MXN, self.s.DECPS);
     }
+    decln
   }
 
   fn allocate_storage(&mut self) {
     self.ipp   = 0;
     self.fline = true;
-    for i in 0..self.fiv.len()-1 {
-      self.fiv[i] = true;
-    }
-    for i in 0..=79 {
+    for i in 0..NFLDS {
       self.lps.push("".to_string());
-      self.lpf.push(0.0);
+      self.lpf.push(Decimal::ZERO);
       self.lis.push("".to_string());
-      self.lif.push(0.0);
-      self.ljf.push(0.0);
-      self.cif.push(0.0);
-      self.cjf.push(0.0);
+      self.lif.push(Decimal::ZERO);
+      self.ljf.push(Decimal::ZERO);
+      self.cif.push(Decimal::ZERO);
+      self.cjf.push(Decimal::ZERO);
+    }
+  }
+}
+
+// Text of one input cell, whatever its type
+fn cell_text(c: &DataType) -> String {
+  match *c {
+    DataType::Empty           => "".to_string(),
+    DataType::String  (ref s) => s.to_string(),
+    DataType::Float   (ref f) |
+    DataType::DateTime(ref f) => format!("{}", f),
+    DataType::Int     (ref i) => format!("{}", i),
+    DataType::Error   (ref e) => format!("{}", e),
+    DataType::Bool    (ref b) => format!("{}", b),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rust_decimal_macros::dec;
+
+  // Sets one invoice line of document 9000000001 with its balances
+  fn invo_line(p: &mut Pagos2Tp, prvam: Decimal, pyamt: Decimal,
+    curam: Decimal) {
+    p.lxs = Default::default();
+    p.lxf = Default::default();
+    p.lxs[CMPNY] = "1000".to_string();
+    p.lxs[DOCNM] = "9000000001".to_string();
+    for (j, amunt) in [(PRVAM, prvam), (PYAMT, pyamt), (CURAM, curam)] {
+      p.lxf[j] = amunt;
+      p.lxs[j] = format!("{:.2}", amunt);
+    }
+    p.ljf[PYAMT] = pyamt;
+  }
+
+  fn merged(lines: &[(Decimal, Decimal, Decimal)]) -> Pagos2Tp {
+    let mut p = Pagos2Tp::new_pagos2();
+    p.allocate_storage();
+    for (k, (prvam, pyamt, curam)) in lines.iter().enumerate() {
+      invo_line(&mut p, *prvam, *pyamt, *curam);
+      if k == 0 {
+        p.store_invo();
+      } else {
+        p.merge_invo(2);
+      }
+    }
+    p
+  }
+
+  // Balances stepping line by line: 224 -> 108 -> 0
+  #[test]
+  fn merge_chained_balances() {
+    let p = merged(&[(dec!(224), dec!(116), dec!(108)),
+      (dec!(108), dec!(108), dec!(0))]);
+    assert_eq!(p.ipp, 1);
+    assert_eq!(p.gif[0][PRVAM], dec!(224));
+    assert_eq!(p.gif[0][PYAMT], dec!(224));
+    assert_eq!(p.gif[0][CURAM], dec!(0));
+    assert!(p.errs.is_empty());
+  }
+
+  // Balances of the document repeated in each line
+  #[test]
+  fn merge_repeated_balances() {
+    let p = merged(&[(dec!(224), dec!(116), dec!(0)),
+      (dec!(224), dec!(108), dec!(0))]);
+    assert_eq!(p.gif[0][PRVAM], dec!(224));
+    assert_eq!(p.gif[0][PYAMT], dec!(224));
+    assert_eq!(p.gif[0][CURAM], dec!(0));
+    assert!(p.errs.is_empty());
+  }
+
+  // Runs one EDICOM input under SKIP policy, with internal tables itabl
+  // added (if any), returns work areas and .lot
+  fn run_skip(name: &str, lines: &[&str], itabl: &str)
+    -> (Pagos2Tp, String) {
+    let dir = std::env::temp_dir().join(format!("pagos2_{}_{}", name,
+      std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let inppt = dir.join(format!("{}.txt", name));
+    fs::write(&inppt, lines.join("\n")).unwrap();
+    let mut s = SettingsTp::new_settings();
+    if !itabl.is_empty() {
+      let itbpt = dir.join("itables.json");
+      fs::write(&itbpt, itabl).unwrap();
+      s.itabl = itbpt.display().to_string();
     }
+    s.IMPTO = IMPTO.to_string();
+    s.TIPOF = TIPOF.to_string();
+    s.OBJIM = OBJIM.to_string();
+    s.DECPS = 2;
+    s.infmt = EDICM.to_string();
+    s.delim = DELIM.to_string();
+    s.encod = ENCOD.to_string();
+    s.onerr = SKIPP.to_string();
+    s.drlay = DRLAY.to_string();
+    s.drmax = 3;
+    s.outnm = "inpfl".to_string();
+    s.renam = RENAM.to_string();
+    s.inppt = inppt.display().to_string();
+    s.outdr = format!("{}{}", dir.display(), std::path::MAIN_SEPARATOR);
+    let mut p = Pagos2Tp::new_pagos2();
+    p.hldms = true;
+    p.proc_indiv_file(s, name, "txt");
+    let lot = fs::read_to_string(dir.join(format!("{}.lot", name)))
+      .unwrap_or_default();
+    fs::remove_dir_all(&dir).unwrap();
+    (p, lot)
+  }
+
+  const TITLE_ROW: &str = "Company Code|Customer|Document Number|\
+    Document Type|Payment Date - Time|Clearing Document|Amount in Doc. Curr|\
+    Document Currency|Eff.exchange rate|Assignment|Forma de Pago|\
+    No. de Parcialidad|Importe Saldo Anterior|Importe Pago|\
+    Importe Saldo Insoluto|Tipo Relacion (04)|Pago Cancelado (Doc Number)|\
+    Num Operacion|RFC Banco Ordenente|Nombre Banco Ordenante|\
+    Cuenta Ordenante|RFC Banco Beneficiario|Cuenta Beneficiario|\
+    Tipo Cadena Pago (01)|Certificado Pago|Cadena Pago|Sello Pago|Tax Code|";
+  const PAYM1: &str = "1000|C001|1400000001|DZ|2022-04-01T10:00:00|\
+    1400000001|348000.00|MXN|1||03|||||||||||||||||";
+  const INVO1: &str = "1000|C001|9000000001|RV|||232000|MXN|1|\
+    00000028-0000-4000-8000-009000000001||1|232000|232000|0|||||||||||||A2|";
+  const INVO2: &str = "1000|C001|9000000002|RV|||116000|MXN|1|\
+    00000029-0000-4000-8000-009000000002||1|116000|116000|0|||||||||||||A2|";
+  const PAYM2: &str = "1000|C002|1400000002|DZ|2022-04-02T10:00:00|\
+    1400000002|116000.00|MXN|1||03|||||||||||||||||";
+  const INVO3: &str = "1000|C002|9000000003|RV|||116000|MXN|1|\
+    0000002A-0000-4000-8000-009000000003||1|116000|116000|0|||||||||||||A2|";
+
+  // Unknown document type inside a payment drops the whole payment, DRs
+  // before the row included, and leaves the next payment as it is
+  #[test]
+  fn skip_payment_of_unknown_doctype() {
+    let unkdt = INVO2.replace("|RV|", "|XX|");
+    let (p, lot) = run_skip("skdt", &[TITLE_ROW, PAYM1, INVO1, &unkdt,
+      INVO2, PAYM2, INVO3], "");
+    assert_eq!(p.errs.iter().filter(|e| e.errcd == UNKDT).count(), 1);
+    assert_eq!((p.npays, p.ndocs), (1, 1));
+    assert!(!lot.contains("1400000001") && !lot.contains("9000000001"));
+    assert!(lot.contains("1400000002") && lot.contains("9000000003"));
+  }
+
+  // Unknown tax code on a DR drops its payment too
+  #[test]
+  fn skip_payment_of_unknown_taxcode() {
+    let unktx = INVO2.replace("|A2|", "|ZZ|");
+    let (p, lot) = run_skip("sktx", &[TITLE_ROW, PAYM1, INVO1, &unktx,
+      PAYM2, INVO3], "");
+    assert_eq!(p.errs.iter().filter(|e| e.errcd == UNKTX).count(), 1);
+    assert_eq!((p.npays, p.ndocs), (1, 1));
+    assert!(!lot.contains("1400000001") && lot.contains("1400000002"));
+  }
+
+  const CUOTA_TABLES: &str = r#"{ "comco": [ { "code":"1000", "taxcd": [
+    { "code":"C1", "taxes": [
+      { "impto":"003", "tipof":"Cuota", "tasac":0.5,  "clase":"T" },
+      { "impto":"002", "tipof":"Tasa",  "tasac":0.16, "clase":"T" } ] } ]
+    } ] }"#;
+  const PAYM3: &str = "1000|C003|1400000003|DZ|2022-04-03T10:00:00|\
+    1400000003|1218.00|MXN|1||03||||||||||||||||||";
+  const INVO4: &str = "1000|C003|9000000004|RV|||1218|MXN|1|\
+    0000002B-0000-4000-8000-009000000004||1|1218|1218|0|||||||||||||C1|";
+
+  // IEPS Cuota of 0.50 on 100 units paid, part of the IVA base: base 1000,
+  // IEPS 50.00, IVA 16% on 1050 = 168.00
+  #[test]
+  fn cuota_base_is_quantity() {
+    let title = format!("{}{}|", TITLE_ROW, QTYTL);
+    let invo = format!("{}100|", INVO4);
+    let (p, lot) = run_skip("cuota", &[&title, PAYM3, &invo], CUOTA_TABLES);
+    assert!(p.errs.is_empty(), "{:?}", p.errs);
+    assert_eq!((p.npays, p.ndocs), (1, 1));
+    assert!(lot.contains("|100.00|003|Cuota|0.500000|50.00|"), "{}", lot);
+    assert!(lot.contains("|1050.00|002|Tasa|0.160000|168.00|"), "{}", lot);
+  }
+
+  // Cuota tax with no quantity in the line drops its payment
+  #[test]
+  fn cuota_needs_quantity() {
+    let (p, _) = run_skip("noqty", &[TITLE_ROW, PAYM3, INVO4], CUOTA_TABLES);
+    assert_eq!(p.errs.iter().filter(|e| e.errcd == NOQTY).count(), 1);
+    assert_eq!(p.npays, 0);
+  }
+
+  #[test]
+  fn merge_balances_not_chained() {
+    let p = merged(&[(dec!(224), dec!(116), dec!(108)),
+      (dec!(150), dec!(50), dec!(100))]);
+    assert_eq!(p.errs.len(), 1);
+    assert_eq!(p.errs[0].errcd, SALDO);
   }
 }
@@ -1,12 +1,15 @@
 // rblib.rs: Library functions for Rust applications [20220406-BAR8TL] ---------
 #![allow(unused)]
 
+use chrono::{Duration, NaiveDate};
+use rust_decimal::{Decimal, RoundingStrategy};
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
 use std::fs;
 
-// Perform rounding of floating point numbers to specific decimal positions ----
-pub fn rb_round(x: f32, y: u32) -> f32 {
-  let y = 10i32.pow(y) as f32;
-  (x * y).round() / y
+// Perform rounding of decimal amounts to specific decimal positions (half up) -
+pub fn rb_round(x: Decimal, y: u32) -> Decimal {
+  x.round_dp_with_strategy(y, RoundingStrategy::MidpointAwayFromZero)
 }
 
 // Determines if a number is into one numbers list -----------------------------
@@ -19,24 +22,266 @@ pub fn contains(s: &Vec<usize>, e: &usize) -> bool {
   return false;
 }
 
-// Indicates if a char string matches one pattern ------------------------------
+// Indicates if a file name passes one input filter ---------------------------
+// Filter is a list of patterns separated by comma. Patterns are globs (* any
+// chars, ? one char, [abc]/[a-z]/[!abc] char sets), case is ignored. Negated
+// patterns !(p1|p2...) or !p exclude names; name must match some of the other
+// patterns, if any, e.g. *.xlsx,*.csv,!(*processed*|~*)
 pub fn pass_filter(ifilt: &String, filen: &str) -> bool {
-  true
+  let filen: Vec<char> = filen.to_lowercase().chars().collect();
+  let mut inclu = false;
+  let mut ninclu = 0;
+  for pattn in split_top(&ifilt.to_lowercase(), ',') {
+    let pattn = pattn.trim();
+    if pattn.is_empty() {
+      continue;
+    }
+    if let Some(negtd) = pattn.strip_prefix('!') {
+      let negtd = negtd.strip_prefix('(').and_then(|n| n.strip_suffix(')'))
+        .unwrap_or(negtd);
+      if split_top(negtd, '|').iter().any(|alt| glob_match(
+        &alt.trim().chars().collect::<Vec<char>>(), &filen)) {
+        return false;
+      }
+    } else {
+      ninclu += 1;
+      if split_top(pattn, '|').iter().any(|alt| glob_match(
+        &alt.trim().chars().collect::<Vec<char>>(), &filen)) {
+        inclu = true;
+      }
+    }
+  }
+  inclu || ninclu == 0
 }
 
-// Rename files ----------------------------------------------------------------
-pub fn ren_file(mode: &str, curdr: String, fnm: &str, fex: &str) {
-  let oldnm = format!("{}{}.{}", curdr, fnm, fex);
-  let mut newnm = oldnm.clone();
-  if mode == "inp" {
-    newnm = format!("{}inp_{}_processed.{}", curdr, fnm, fex);
-  } else if mode == "out" {
-    newnm = format!("{}out_{}.lot", curdr, fnm);
+// Splits a filter at sepch, except inside parentheses or brackets
+fn split_top(text: &str, sepch: char) -> Vec<String> {
+  let mut parts: Vec<String> = vec![String::new()];
+  let mut depth: i32 = 0;
+  for c in text.chars() {
+    match c {
+      '(' | '[' => depth += 1,
+      ')' | ']' => depth -= 1,
+      _         => {},
+    };
+    if c == sepch && depth == 0 {
+      parts.push(String::new());
+    } else {
+      parts.last_mut().unwrap().push(c);
+    }
   }
+  parts
+}
+
+// Matches a text against one glob pattern (*, ?, [...])
+fn glob_match(pattn: &[char], text: &[char]) -> bool {
+  match pattn.first() {
+    None      => text.is_empty(),
+    Some('*') => (0..=text.len()).any(|i| glob_match(&pattn[1..], &text[i..])),
+    Some('?') => !text.is_empty() && glob_match(&pattn[1..], &text[1..]),
+    Some('[') => {
+      let close = match pattn.iter().skip(2).position(|c| *c == ']') {
+        Some(i) => i + 2,
+        None    => return text.first() == Some(&'[') &&
+          glob_match(&pattn[1..], &text[1..]),
+      };
+      !text.is_empty() && in_charset(&pattn[1..close], text[0]) &&
+        glob_match(&pattn[close + 1..], &text[1..])
+    },
+    Some(c)   => text.first() == Some(c) && glob_match(&pattn[1..], &text[1..]),
+  }
+}
+
+// Tells if a char belongs to set [abc], [a-z], negated by leading ! or ^
+fn in_charset(set: &[char], c: char) -> bool {
+  let (negtd, set) = match set.first() {
+    Some('!') | Some('^') => (true, &set[1..]),
+    _                     => (false, set),
+  };
+  let mut found = false;
+  let mut i = 0;
+  while i < set.len() {
+    if i + 2 < set.len() && set[i + 1] == '-' {
+      found |= set[i] <= c && c <= set[i + 2];
+      i += 3;
+    } else {
+      found |= set[i] == c;
+      i += 1;
+    }
+  }
+  found != negtd
+}
+
+// Converts an Excel date/time serial number to ISO format yyyy-mm-ddThh:mm:ss.
+// Values that are not serial numbers (or out of Excel dates range, up to
+// 9999-12-31) are returned as they come --------------------------------------
+pub fn xl_datetime(val: &str) -> String {
+  match val.trim().parse::<f64>() {
+    Ok(serno) if (0.0..2958466.0).contains(&serno) => {
+      let secs = (serno * 86400.0).round() as i64;
+      let dtime = NaiveDate::from_ymd_opt(1899, 12, 30)
+        .and_then(|d| d.and_hms_opt(0, 0, 0)).unwrap() +
+        Duration::seconds(secs);
+      dtime.format("%Y-%m-%dT%H:%M:%S").to_string()
+    },
+    _ => val.trim().to_string(),
+  }
+}
+
+// Numeric catalog keys come from Excel without leading zeros (3 -> "03") -----
+pub fn fmt_code(val: &str) -> String {
+  let val = val.trim();
+  match val.parse::<u32>() {
+    Ok(n)  => format!("{:02}", n),
+    Err(_) => val.to_string(),
+  }
+}
+
+// Checks RFC format: 3 (moral) or 4 (fisica) letters, date yymmdd, homoclave -
+pub fn is_rfc(val: &str) -> bool {
+  let chars: Vec<char> = val.trim().to_uppercase().chars().collect();
+  if chars.len() != 12 && chars.len() != 13 {
+    return false;
+  }
+  let nlets = chars.len() - 9;
+  chars[..nlets].iter().all(|c| c.is_ascii_uppercase() || *c == '&' ||
+    *c == '\u{d1}') &&
+  chars[nlets..nlets + 6].iter().all(|c| c.is_ascii_digit()) &&
+  chars[nlets + 6..].iter().all(|c| c.is_ascii_alphanumeric())
+}
+
+// Normalizes a column title for matching: no accents, lowercase, and runs of
+// other than letters and digits turned into one blank -------------------------
+pub fn norm_title(title: &str) -> String {
+  let plain: String = title.nfd().filter(|c| !is_combining_mark(*c))
+    .collect::<String>().to_lowercase();
+  plain.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty())
+    .collect::<Vec<&str>>().join(" ")
+}
+
+// Rename files ----------------------------------------------------------------
+pub fn ren_file(oldnm: &str, newnm: &str) {
   fs::rename(oldnm, newnm).expect("File rename failure");
 }
 
+// Builds a file name out of a naming template --------------------------------
+// Tokens are replaced by their values and literals go in single quotes, parts
+// optionally joined by '+', e.g. dtsys'_'+inpfl'_out' -> 20220406093000_aa_out
+pub fn fmt_name(templ: &str, tokns: &[(&str, String)])
+  -> Result<String, String> {
+  let mut name = String::new();
+  let mut chars = templ.chars().peekable();
+  while let Some(c) = chars.next() {
+    if c == '+' || c.is_whitespace() {
+      continue;
+    }
+    if c == '\'' {
+      let mut litrl = String::new();
+      loop {
+        match chars.next() {
+          Some('\'') => break,
+          Some(c)    => litrl.push(c),
+          None       => return Err(format!("Naming '{}': literal not closed",
+            templ)),
+        };
+      }
+      name.push_str(&litrl);
+      continue;
+    }
+    let mut tokn = c.to_string();
+    while let Some(c) = chars.peek() {
+      if *c == '+' || *c == '\'' || c.is_whitespace() {
+        break;
+      }
+      tokn.push(*c);
+      chars.next();
+    }
+    match tokns.iter().find(|(t, _)| *t == tokn) {
+      Some((_, val)) => name.push_str(val),
+      None           => return Err(format!("Naming '{}': token '{}' not \
+        known", templ, tokn)),
+    };
+  }
+  if name.is_empty() {
+    return Err(format!("Naming '{}' gives an empty name", templ));
+  }
+  Ok(name)
+}
+
 // Display the data type of one object -----------------------------------------
 pub fn print_type_of<T>(_: &T) {
   println!("{}", std::any::type_name::<T>());
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn tokns() -> Vec<(&'static str, String)> {
+    vec![("dtsys", "20220406093000".to_string()), ("inpfl", "aa".to_string()),
+      ("cmpny", "1000".to_string())]
+  }
+
+  #[test]
+  fn name_tokens_and_literals() {
+    assert_eq!(fmt_name("dtsys'_'+inpfl'_out'", &tokns()),
+      Ok("20220406093000_aa_out".to_string()));
+    assert_eq!(fmt_name("'out_'+inpfl", &tokns()), Ok("out_aa".to_string()));
+    assert_eq!(fmt_name("cmpny '-' inpfl", &tokns()),
+      Ok("1000-aa".to_string()));
+    assert_eq!(fmt_name("inpfl'_a+b c'", &tokns()),
+      Ok("aa_a+b c".to_string()));
+  }
+
+  #[test]
+  fn name_errors() {
+    assert!(fmt_name("inpfl'_out", &tokns()).is_err());
+    assert!(fmt_name("inpfl+seqnr", &tokns()).is_err());
+    assert!(fmt_name("''", &tokns()).is_err());
+    assert!(fmt_name("", &tokns()).is_err());
+  }
+
+  fn glob(pattn: &str, text: &str) -> bool {
+    glob_match(&pattn.chars().collect::<Vec<char>>(),
+      &text.chars().collect::<Vec<char>>())
+  }
+
+  #[test]
+  fn glob_wildcards_and_sets() {
+    assert!(glob("*.xlsx", "aa.xlsx"));
+    assert!(glob("a?.csv", "ab.csv"));
+    assert!(!glob("a?.csv", "a.csv"));
+    assert!(glob("pay[0-9].lot", "pay7.lot"));
+    assert!(!glob("pay[!0-9].lot", "pay7.lot"));
+    assert!(glob("pay[^0-9].lot", "payx.lot"));
+    assert!(glob("[ab]*", "b1.txt"));
+    assert!(!glob("[ab]*", "c1.txt"));
+    assert!(glob("a[b", "a[b"));
+  }
+
+  #[test]
+  fn filter_negation_and_alternation() {
+    let ifilt = "*.xlsx,*.csv,!(*processed*|~*)".to_string();
+    assert!(pass_filter(&ifilt, "aa.xlsx"));
+    assert!(pass_filter(&ifilt, "aa.csv"));
+    assert!(!pass_filter(&ifilt, "aa.ods"));
+    assert!(!pass_filter(&ifilt, "aa_inp_processed.xlsx"));
+    assert!(!pass_filter(&ifilt, "~$aa.xlsx"));
+    let ifilt = "!(*processed*)".to_string();
+    assert!(pass_filter(&ifilt, "aa.lot"));
+    assert!(!pass_filter(&ifilt, "aa_processed.lot"));
+    let ifilt = "!~*".to_string();
+    assert!(!pass_filter(&ifilt, "~aa.xlsx"));
+    assert!(pass_filter(&"aa*|bb*".to_string(), "bb.csv"));
+    assert!(!pass_filter(&"aa*|bb*".to_string(), "cc.csv"));
+  }
+
+  #[test]
+  fn filter_ignores_case() {
+    let ifilt = "*.XLSX,!*Processed*".to_string();
+    assert!(pass_filter(&ifilt, "AA.xlsx"));
+    assert!(pass_filter(&ifilt, "aa.Xlsx"));
+    assert!(!pass_filter(&ifilt, "aa_PROCESSED.xlsx"));
+    assert!(pass_filter(&"pay[a-c]*".to_string(), "PAYB.csv"));
+  }
+}
@@ -0,0 +1,75 @@
+// rounding.rs: SAT Pagos 2.0 rounding and tolerance rules for tax amounts -----
+// [20220406-BAR8TL]
+#![allow(unused)]
+
+use crate::rblib::*;
+use rust_decimal::{Decimal, RoundingStrategy};
+use rust_decimal_macros::dec;
+
+// Lower limit for a tax amount: (base - 10^-d / 2) * rate, truncated ---------
+pub fn lim_inf(base: Decimal, rate: Decimal, decim: u32) -> Decimal {
+  let halfu = Decimal::new(5, decim + 1);
+  ((base - halfu) * rate)
+    .round_dp_with_strategy(decim, RoundingStrategy::ToNegativeInfinity)
+}
+
+// Upper limit for a tax amount: (base + 10^-d / 2 - 10^-12) * rate, rounded up
+pub fn lim_sup(base: Decimal, rate: Decimal, decim: u32) -> Decimal {
+  let halfu = Decimal::new(5, decim + 1);
+  ((base + halfu - dec!(0.000000000001)) * rate)
+    .round_dp_with_strategy(decim, RoundingStrategy::ToPositiveInfinity)
+}
+
+// Rounds a tax amount and brings it into its tolerance limits. Second value of
+// the result tells if the amount was out of limits and had to be adjusted ----
+pub fn rnd_taxamt(amunt: Decimal, base: Decimal, rate: Decimal, decim: u32)
+  -> (Decimal, bool) {
+  let amunt = rb_round(amunt, decim);
+  let linf  = lim_inf(base, rate, decim);
+  let lsup  = lim_sup(base, rate, decim);
+  if amunt < linf {
+    return (linf, true);
+  }
+  if amunt > lsup {
+    return (lsup, true);
+  }
+  (amunt, false)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Limits for Importe of a traslado at 16% with 2 decimals, by SAT rules:
+  // base 1000.00 gives 159.99..160.01, base 46.55 gives 7.44..7.45
+  #[test]
+  fn limits_sat_examples() {
+    assert_eq!(lim_inf(dec!(1000.00), dec!(0.16), 2), dec!(159.99));
+    assert_eq!(lim_sup(dec!(1000.00), dec!(0.16), 2), dec!(160.01));
+    assert_eq!(lim_inf(dec!(46.55), dec!(0.16), 2), dec!(7.44));
+    assert_eq!(lim_sup(dec!(46.55), dec!(0.16), 2), dec!(7.45));
+  }
+
+  // Currencies with no decimals (e.g. JPY) widen the limits to whole units
+  #[test]
+  fn limits_no_decimals() {
+    assert_eq!(lim_inf(dec!(1000), dec!(0.16), 0), dec!(159));
+    assert_eq!(lim_sup(dec!(1000), dec!(0.16), 0), dec!(161));
+  }
+
+  #[test]
+  fn taxamt_within_limits() {
+    assert_eq!(rnd_taxamt(dec!(160.004), dec!(1000.00), dec!(0.16), 2),
+      (dec!(160.00), false));
+    assert_eq!(rnd_taxamt(dec!(160.01), dec!(1000.00), dec!(0.16), 2),
+      (dec!(160.01), false));
+  }
+
+  #[test]
+  fn taxamt_out_of_limits() {
+    assert_eq!(rnd_taxamt(dec!(160.05), dec!(1000.00), dec!(0.16), 2),
+      (dec!(160.01), true));
+    assert_eq!(rnd_taxamt(dec!(159.90), dec!(1000.00), dec!(0.16), 2),
+      (dec!(159.99), true));
+  }
+}
@@ -5,6 +5,7 @@
 #![allow(non_camel_case_types)]
 
 use crate::fixvalues::*;
+use crate::rblib::fmt_name;
 use chrono::Local;
 use chrono::NaiveDateTime;
 use chrono::{Datelike, Duration, NaiveDate};
@@ -24,17 +25,31 @@ pub struct SettingsTp {
   pub TAB  : String,       // TAB
   pub DEC  : String,       // DEC
   pub DECPS: u32,          // DECIMAL_POS
-  pub inpdr: String,       // INPUTS_DIR
+  pub inpdr: String,       // INPUTS_DIR (BATCH: several, separated by ;)
   pub outdr: String,       // OUTPUTS_DIR
   pub ifilt: String,       // INPUTS_FILTER
   pub renam: String,       // RENAME_FILES
+  pub recur: String,       // RECURSIVE_SCAN
+  pub wrkrs: usize,        // BATCH_WORKERS (0 = one per CPU)
   pub inpnm: String,       // INPUTS_NAMING
   pub outnm: String,       // OUTPUTS_NAMING
+  pub itabl: String,       // ITABLES_FILE
+  pub catlg: String,       // CATALOGS_FILE
+  pub infmt: String,       // INPUT_FORMAT
+  pub delim: String,       // INPUT_DELIMITER
+  pub encod: String,       // INPUT_ENCODING
+  pub sheet: String,       // WORKSHEETS
+  pub onerr: String,       // ON_ERROR
+  pub drlay: String,       // DR_TAXES_LAYOUT
+  pub drmax: usize,        // DR_TAXES_MAXIMUM
+  pub outfm: String,       // OUTPUT_FORMATS
+  pub olayt: String,       // OUTPUT_LAYOUT
   pub optin: String,       // OPTION
   pub objnm: String,       // OBJECT_NAME
   pub modep: String,       // MODE
   pub inpfl: String,       // INPUT_FILE
   pub inppt: String,       // INPUT_PATH
+  pub seqnr: usize,        // Sequence number of input file in the run
   pub found: bool,
   pub dtsys: NaiveDateTime
 }
@@ -69,12 +84,39 @@ impl SettingsTp {
       { c.progm.ifilt.clone() } else { IFILT.to_string() };
     self.renam = if c.progm.renam.len() > 0
       { c.progm.renam.clone() } else { RENAM.to_string() };
+    self.recur = if !c.progm.recur.is_empty()
+      { c.progm.recur.clone() } else { RECUR.to_string() };
+    self.wrkrs = if !c.progm.wrkrs.is_empty()
+      { c.progm.wrkrs.trim().parse().expect("wrkrs must be a number") }
+      else { WRKRS.parse().unwrap() };
     self.inpnm = if c.progm.inpnm.len() > 0
       { c.progm.inpnm.clone() } else { INPNM.to_string() };
     self.outnm = if c.progm.outnm.len() > 0
       { c.progm.outnm.clone() } else { OUTNM.to_string() };
+    self.itabl = if !c.progm.itabl.is_empty()
+      { c.progm.itabl.clone() } else { ITABL.to_string() };
+    self.catlg = if !c.progm.catlg.is_empty()
+      { c.progm.catlg.clone() } else { CATLG.to_string() };
+    self.infmt = if !c.progm.infmt.is_empty()
+      { c.progm.infmt.to_lowercase() } else { INFMT.to_string() };
+    self.delim = if !c.progm.delim.is_empty()
+      { c.progm.delim.clone() } else { DELIM.to_string() };
+    self.encod = if !c.progm.encod.is_empty()
+      { c.progm.encod.to_lowercase() } else { ENCOD.to_string() };
+    self.onerr = if !c.progm.onerr.is_empty()
+      { c.progm.onerr.to_uppercase() } else { ONERR.to_string() };
+    self.drlay = if !c.progm.drlay.is_empty()
+      { c.progm.drlay.to_uppercase() } else { DRLAY.to_string() };
+    self.drmax = if !c.progm.drmax.is_empty()
+      { c.progm.drmax.trim().parse().expect("drmax must be a number") }
+      else { DRMAX.parse().unwrap() };
+    self.outfm = if !c.progm.outfm.is_empty()
+      { c.progm.outfm.to_lowercase() } else { OUTFM.to_string() };
+    self.olayt = if !c.progm.olayt.is_empty()
+      { c.progm.olayt.clone() } else { OLAYT.to_string() };
     self.DECPS = DEC.trim().parse().unwrap();
     self.dtsys = Local::now().naive_local();
+    self.check_naming();
   }
 
   pub fn set_runvars(&mut self, p: ParameTp) {
@@ -94,16 +136,80 @@ impl SettingsTp {
             { run.modep.clone() } else { INDIV.to_string() };
           self.inpfl = if run.inpfl.len() > 0
             { run.inpfl.clone() } else { SAMPL.to_string() };
+          if !run.ifilt.is_empty() {
+            self.ifilt = run.ifilt.clone();
+          }
           if run.renam.len() > 0 {
             self.renam = run.renam.clone();
           }
+          if !run.recur.is_empty() {
+            self.recur = run.recur.clone();
+          }
+          if !run.wrkrs.is_empty() {
+            self.wrkrs = run.wrkrs.trim().parse()
+              .expect("wrkrs must be a number");
+          }
+          if !run.inpnm.is_empty() {
+            self.inpnm = run.inpnm.clone();
+          }
+          if !run.outnm.is_empty() {
+            self.outnm = run.outnm.clone();
+          }
+          self.check_naming();
           if run.inpdr.len() > 0 {
             self.inpdr = run.inpdr.clone();
           }
           if run.outdr.len() > 0 {
             self.outdr = run.outdr.clone();
           }
-          self.inppt = format!("{}{}", self.inpdr, self.inpfl);
+          if !run.itabl.is_empty() {
+            self.itabl = run.itabl.clone();
+          }
+          if !run.catlg.is_empty() {
+            self.catlg = run.catlg.clone();
+          }
+          if !run.infmt.is_empty() {
+            self.infmt = run.infmt.to_lowercase();
+          }
+          if !INFMTS.contains(&self.infmt.as_str()) {
+            panic!("Error: Input format must be one of {}", INFMTS.join("/"));
+          }
+          if !run.delim.is_empty() {
+            self.delim = run.delim.clone();
+          }
+          if !run.encod.is_empty() {
+            self.encod = run.encod.to_lowercase();
+          }
+          self.sheet = if !run.sheet.is_empty()
+            { run.sheet.clone() } else { self.TAB.clone() };
+          if !run.onerr.is_empty() {
+            self.onerr = run.onerr.to_uppercase();
+          }
+          if self.onerr != ABORT && self.onerr != SKIPP && self.onerr != WARNG {
+            panic!("Error: On-error policy must be {}, {} or {}", ABORT, SKIPP,
+              WARNG);
+          }
+          if !run.drlay.is_empty() {
+            self.drlay = run.drlay.to_uppercase();
+          }
+          if self.drlay != ROWS && self.drlay != COLS {
+            panic!("Error: DR taxes layout must be {} or {}", ROWS, COLS);
+          }
+          if !run.drmax.is_empty() {
+            self.drmax = run.drmax.trim().parse()
+              .expect("drmax must be a number");
+          }
+          if !run.outfm.is_empty() {
+            self.outfm = run.outfm.to_lowercase();
+          }
+          if !run.olayt.is_empty() {
+            self.olayt = run.olayt.clone();
+          }
+          if !self.olayt.is_empty() && self.seek_layout().is_none() {
+            panic!("Error: Output layout {} not found in config", self.olayt);
+          }
+          let inpdr = self.inpdr.split(DIRSP).next().unwrap_or("").trim();
+          self.inppt = format!("{}{}", inpdr, self.inpfl);
           println!("{}", self.inppt);
           self.found = true;
           break;
@@ -111,6 +217,34 @@ impl SettingsTp {
       }
     }
   }
+
+  // Stops the run when naming templates inpnm or outnm are not valid, before
+  // any file is processed
+  fn check_naming(&self) {
+    for templ in [&self.inpnm, &self.outnm] {
+      if let Err(e) = fmt_name(templ, &self.name_tokens("-", "-")) {
+        panic!("Error: {}", e);
+      }
+    }
+  }
+
+  // Values of the tokens allowed in naming templates inpnm and outnm: system
+  // date-time, input file stem, object name, company code of the first payment
+  // and sequence number of the input file in the run
+  pub fn name_tokens(&self, inpfl: &str, cmpny: &str) -> Vec<(&str, String)> {
+    vec![
+      ("dtsys", self.dtsys.format("%Y%m%d%H%M%S").to_string()),
+      ("inpfl", inpfl.to_string()),
+      ("objnm", self.objnm.clone()),
+      ("cmpny", cmpny.to_string()),
+      ("seqnr", format!("{:03}", self.seqnr))
+    ]
+  }
+
+  // Output record layout selected for the run, if any
+  pub fn seek_layout(&self) -> Option<&LayoutTp> {
+    self.cfd.layot.iter().find(|l| l.name == self.olayt)
+  }
 }
 
 // config.rs: Reads config file and gets run parameter -------------------------
@@ -139,9 +273,33 @@ pub struct ProgmTp { // progm
   #[serde(default)]
   pub renam: String, // rename_files
   #[serde(default)]
+  pub recur: String, // recursive_scan
+  #[serde(default)]
+  pub wrkrs: String, // batch_workers
+  #[serde(default)]
   pub inpnm: String, // inputs_naming
   #[serde(default)]
-  pub outnm: String  // outputs_naming
+  pub outnm: String, // outputs_naming
+  #[serde(default)]
+  pub itabl: String, // itables_file
+  #[serde(default)]
+  pub catlg: String, // catalogs_file
+  #[serde(default)]
+  pub infmt: String, // input_format
+  #[serde(default)]
+  pub delim: String, // input_delimiter
+  #[serde(default)]
+  pub encod: String, // input_encoding
+  #[serde(default)]
+  pub onerr: String, // on_error
+  #[serde(default)]
+  pub drlay: String, // dr_taxes_layout
+  #[serde(default)]
+  pub drmax: String, // dr_taxes_maximum
+  #[serde(default)]
+  pub outfm: String, // output_formats
+  #[serde(default)]
+  pub olayt: String  // output_layout
 }
 
 #[derive(Debug, Clone, Default, Deserialize)]
@@ -161,16 +319,125 @@ pub struct RunTp {   // run
   #[serde(default)]
   pub renam: String, // rename_files
   #[serde(default)]
+  pub recur: String, // recursive_scan
+  #[serde(default)]
+  pub wrkrs: String, // batch_workers
+  #[serde(default)]
   pub inpnm: String, // inputs_naming
   #[serde(default)]
-  pub outnm: String  // outputs_naming
+  pub outnm: String, // outputs_naming
+  #[serde(default)]
+  pub itabl: String, // itables_file
+  #[serde(default)]
+  pub catlg: String, // catalogs_file
+  #[serde(default)]
+  pub infmt: String, // input_format
+  #[serde(default)]
+  pub delim: String, // input_delimiter
+  #[serde(default)]
+  pub encod: String, // input_encoding
+  #[serde(default)]
+  pub sheet: String, // worksheets
+  #[serde(default)]
+  pub onerr: String, // on_error
+  #[serde(default)]
+  pub drlay: String, // dr_taxes_layout
+  #[serde(default)]
+  pub drmax: String, // dr_taxes_maximum
+  #[serde(default)]
+  pub outfm: String, // output_formats
+  #[serde(default)]
+  pub olayt: String  // output_layout
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EmisorTp {  // cfdix.emisr - issuer data per company code
+  pub code : String, // company_code
+  #[serde(default)]
+  pub rfc  : String, // rfc
+  #[serde(default)]
+  pub nombr: String, // nombre
+  #[serde(default)]
+  pub regim: String, // regimen_fiscal
+  #[serde(default)]
+  pub lugex: String  // lugar_expedicion
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ReceptorTp { // cfdix.recep - receiver data per customer
+  pub code : String, // customer
+  #[serde(default)]
+  pub rfc  : String, // rfc
+  #[serde(default)]
+  pub nombr: String, // nombre
+  #[serde(default)]
+  pub regim: String, // regimen_fiscal
+  #[serde(default)]
+  pub domfi: String  // domicilio_fiscal (zip code)
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CfdixTp {   // cfdix
+  #[serde(default)]
+  pub emisr: Vec<EmisorTp>,
+  #[serde(default)]
+  pub recep: Vec<ReceptorTp>
+}
+
+impl CfdixTp {
+  // Issuer data of a company code, wildcard entry "*" when not found
+  pub fn seek_emisr(&self, cmpny: &str) -> EmisorTp {
+    match self.emisr.iter().find(|e| e.code == cmpny) {
+      Some(e) => e.clone(),
+      None    => self.emisr.iter().find(|e| e.code == WILDC).cloned()
+        .unwrap_or_default(),
+    }
+  }
+
+  // Receiver data of a customer, wildcard entry "*" when not found
+  pub fn seek_recep(&self, custo: &str) -> ReceptorTp {
+    match self.recep.iter().find(|r| r.code == custo) {
+      Some(r) => r.clone(),
+      None    => self.recep.iter().find(|r| r.code == WILDC).cloned()
+        .unwrap_or_default(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AliasTp {   // alias - other titles of one input column
+  pub title: String,      // field title, as in output title line
+  pub names: Vec<String>  // titles accepted for it in inputs
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LayoutTp {  // layot - record layout of .lot output file
+  pub name : String,      // layout name, as given in olayt
+  #[serde(default)]
+  pub title: Vec<String>, // field titles of title record, in order
+  #[serde(default)]
+  pub paymt: Vec<String>, // field titles of payment record
+  #[serde(default)]
+  pub invoi: Vec<String>, // field titles of DR record
+  #[serde(default)]
+  pub delim: String,      // fields delimiter
+  #[serde(default)]
+  pub trail: String,      // delimiter after last field (yes/no)
+  #[serde(default)]
+  pub lnend: String       // line ending (CRLF/LF/CR)
 }
 
 #[derive(Debug, Clone, Default, Deserialize)]
 pub struct ConfigTp {
   pub konst: KonstTp,
   pub progm: ProgmTp,
-  pub run  : Vec<RunTp>
+  pub run  : Vec<RunTp>,
+  #[serde(default)]
+  pub cfdix: CfdixTp,
+  #[serde(default)]
+  pub alias: Vec<AliasTp>,
+  #[serde(default)]
+  pub layot: Vec<LayoutTp>
 }
 
 impl ConfigTp {
@@ -186,6 +453,9 @@ impl ConfigTp {
     self.konst = cfg.konst;
     self.progm = cfg.progm;
     self.run   = cfg.run;
+    self.cfdix = cfg.cfdix;
+    self.alias = cfg.alias;
+    self.layot = cfg.layot;
   }
 }
 
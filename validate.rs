@@ -0,0 +1,161 @@
+// validate.rs: Checks built payments against SAT Pagos 2.0 validation rules --
+// [20220406-BAR8TL]
+#![allow(unused)]
+
+use crate::fixvalues::*;
+use crate::pagos2::{Pagos2Tp, ValerrTp};
+use crate::rblib::*;
+use crate::rounding::*;
+use chrono::NaiveDateTime;
+use rust_decimal::Decimal;
+
+// Runs all rules over the payment in process and its DRs, returns findings --
+pub fn val_payment(p: &Pagos2Tp) -> Vec<ValerrTp> {
+  let mut errs: Vec<ValerrTp> = Vec::new();
+  val_sumpaid(p, &mut errs);
+  val_formapago(p, &mut errs);
+  val_rfcbanks(p, &mut errs);
+  val_schema(p, &mut errs);
+  for i in 0..p.ipp {
+    val_saldo(p, i, &mut errs);
+    val_parcial(p, i, &mut errs);
+    val_iddocum(p, i, &mut errs);
+  }
+  errs
+}
+
+// Sum of ImpPagado of the DRs (converted with EquivalenciaDR) must match Monto
+// of the payment, allowing one unit of last decimal per DR for rounding
+fn val_sumpaid(p: &Pagos2Tp, errs: &mut Vec<ValerrTp>) {
+  let decpy = p.c.cur_decim(&p.lps[CURCY], p.s.DECPS);
+  let sumpd = rb_round(p.cjf[TPAYM], decpy);
+  let toler = Decimal::new(p.ipp as i64, decpy);
+  if (sumpd - p.lpf[AMOUN]).abs() > toler {
+    add_finding(p, errs, &p.lps[DOCNM], SUMPG, format!("Sum of ImpPagado {} \
+      differs from Monto {} by more than {}", sumpd, p.lpf[AMOUN], toler));
+  }
+}
+
+// FormaDePagoP must be a key of c_FormaPago other than 99 (Por definir)
+fn val_formapago(p: &Pagos2Tp, errs: &mut Vec<ValerrTp>) {
+  let frmpg = fmt_code(&p.lps[PYFRM]);
+  if !p.c.is_formapago(&frmpg) || frmpg == FPDEF {
+    add_finding(p, errs, &p.lps[DOCNM], FRMPG,
+      format!("FormaDePagoP '{}' not in catalog c_FormaPago", frmpg));
+  }
+}
+
+// RFC of ordering and beneficiary banks, when given, must be well formed
+fn val_rfcbanks(p: &Pagos2Tp, errs: &mut Vec<ValerrTp>) {
+  for (col, errcd, what) in [(RFCOR, RFCOB, "RfcEmisorCtaOrd"),
+    (RFCBF, RFCBB, "RfcEmisorCtaBen")] {
+    let rfc = p.lps[col].trim();
+    if !rfc.is_empty() && !is_rfc(rfc) {
+      add_finding(p, errs, &p.lps[DOCNM], errcd,
+        format!("{} '{}' is not a valid RFC", what, rfc));
+    }
+  }
+}
+
+// Payment values taken from input must keep within facets of Pagos20.xsd
+// (formats, lengths, catalogs, ranges). No schema engine is run: these are the
+// facets the values of the input can break
+fn val_schema(p: &Pagos2Tp, errs: &mut Vec<ValerrTp>) {
+  let fecha = xl_datetime(&p.lps[PYDTE]);
+  if !is_isodtime(&fecha) {
+    add_finding(p, errs, &p.lps[DOCNM], XSDPG,
+      format!("FechaPago '{}' not in format yyyy-mm-ddThh:mm:ss", fecha));
+  }
+  // Built-in c_Moneda holds only the currencies most used: out of it, a code
+  // is an error only against a full catalog given in catalogs file (else it
+  // is left to warning UNKCY of the line)
+  let moned = p.lps[CURCY].trim();
+  let known = p.c.seek_moneda(moned).is_some() || (p.s.catlg.is_empty() &&
+    moned.len() == 3 && moned.chars().all(|c| c.is_ascii_uppercase()));
+  if !known || moned == "XXX" {
+    add_finding(p, errs, &p.lps[DOCNM], XSDPG,
+      format!("MonedaP '{}' not in catalog c_Moneda", moned));
+  }
+  if moned != MXN && p.lpf[EXCHG] <= Decimal::ZERO {
+    add_finding(p, errs, &p.lps[DOCNM], XSDPG,
+      format!("TipoCambioP must be greater than 0 for MonedaP {}", moned));
+  }
+  if p.lpf[AMOUN] <= Decimal::ZERO {
+    add_finding(p, errs, &p.lps[DOCNM], XSDPG,
+      format!("Monto {} must be greater than 0", p.lpf[AMOUN]));
+  }
+  for (col, what) in [(CTAOR, "CtaOrdenante"), (CTABF, "CtaBeneficiario")] {
+    let cta = p.lps[col].trim();
+    if !cta.is_empty() && (cta.len() < 10 || cta.len() > 50 || !cta.chars()
+      .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')) {
+      add_finding(p, errs, &p.lps[DOCNM], XSDPG, format!("{} '{}' must be \
+        10 to 50 capital letters, digits or _", what, cta));
+    }
+  }
+}
+
+// IdDocumento (assignment) must be the UUID of the invoice, or the folio
+// 999-99-999999999 of documents not issued as CFDI
+fn val_iddocum(p: &Pagos2Tp, i: usize, errs: &mut Vec<ValerrTp>) {
+  let lis = &p.gis[i];
+  let iddoc = lis[ASGMT].trim();
+  if !is_mask(iddoc, "hhhhhhhh-hhhh-hhhh-hhhh-hhhhhhhhhhhh") &&
+    !is_mask(iddoc, "ddd-dd-ddddddddd") {
+    add_finding(p, errs, &lis[DOCNM], IDDOC, format!("IdDocumento '{}' is \
+      neither a UUID nor a folio 999-99-999999999", iddoc));
+  }
+}
+
+// ImpSaldoAnt - ImpPagado must equal ImpSaldoInsoluto, which cannot be negative
+fn val_saldo(p: &Pagos2Tp, i: usize, errs: &mut Vec<ValerrTp>) {
+  let (lis, lif) = (&p.gis[i], &p.gif[i]);
+  let decdr = p.c.cur_decim(&lis[CURCY], p.s.DECPS);
+  let saldo = rb_round(lif[PRVAM] - lif[PYAMT], decdr);
+  let insol = rb_round(lif[CURAM], decdr);
+  if saldo != insol || insol < Decimal::ZERO {
+    add_finding(p, errs, &lis[DOCNM], SALDO, format!("ImpSaldoAnt {} - \
+      ImpPagado {} does not match ImpSaldoInsoluto {}", lif[PRVAM],
+      lif[PYAMT], lif[CURAM]));
+  }
+}
+
+// NumParcialidad must be an integer greater than zero
+fn val_parcial(p: &Pagos2Tp, i: usize, errs: &mut Vec<ValerrTp>) {
+  let lis = &p.gis[i];
+  match lis[PARTL].trim().parse::<u32>() {
+    Ok(n) if n > 0 => {},
+    _              => {
+      add_finding(p, errs, &lis[DOCNM], PARCL,
+        format!("NumParcialidad '{}' must be greater than 0", lis[PARTL]));
+    },
+  };
+}
+
+// Date and time as yyyy-mm-ddThh:mm:ss, valid as such
+fn is_isodtime(val: &str) -> bool {
+  NaiveDateTime::parse_from_str(val, "%Y-%m-%dT%H:%M:%S").is_ok() &&
+    is_mask(val, "dddd-dd-ddTdd:dd:dd")
+}
+
+// Checks val against mask: d digit, h hexadecimal digit, other chars as is
+fn is_mask(val: &str, mask: &str) -> bool {
+  val.len() == mask.len() && val.chars().zip(mask.chars()).all(|(c, m)|
+    match m {
+      'd' => c.is_ascii_digit(),
+      'h' => c.is_ascii_hexdigit(),
+        _ => c == m,
+    })
+}
+
+// Findings are referred to the payment row, and to the DR document if any
+fn add_finding(p: &Pagos2Tp, errs: &mut Vec<ValerrTp>, docnm: &str,
+  errcd: &str, messg: String) {
+  errs.push(ValerrTp {
+    rownr: p.prwnr,
+    cmpny: p.lps[CMPNY].clone(),
+    docnm: docnm.to_string(),
+    errcd: errcd.to_string(),
+    messg,
+    warng: false
+  });
+}
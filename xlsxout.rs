@@ -0,0 +1,69 @@
+// xlsxout.rs: Writes output lines of one input file as an Excel workbook -----
+// [20220406-BAR8TL]
+#![allow(unused)]
+
+use crate::fixvalues::*;
+use crate::pagos2::Pagos2Tp;
+use crate::rblib::*;
+use rust_xlsxwriter::{Color, Format, Workbook, Worksheet};
+
+#[derive(Debug, Clone, Default)]
+pub struct XlsxoutTp {
+  pub nrows: u32               // Rows written
+}
+
+impl XlsxoutTp {
+  pub fn new_xlsxout() -> XlsxoutTp {
+    XlsxoutTp { ..Default::default() }
+  }
+
+  // Writes title, payment and DR lines kept while building the .lot file, with
+  // same columns. Amounts and rates go as numbers, payment lines highlighted
+  // and title line frozen. Returns the name of the file written
+  pub fn write_xlsx(&mut self, p: &Pagos2Tp) -> String {
+    let flnam = format!("{}{}.xlsx", p.s.outdr, p.ostem);
+    let ftitl = Format::new().set_bold().set_background_color(Color::Silver);
+    let fpaym = Format::new().set_bold()
+      .set_background_color(Color::RGB(0xDDEBF7));
+    let fdrln = Format::new();
+    let mut wbook = Workbook::new();
+    let wsheet = wbook.add_worksheet();
+    wsheet.set_name(TAB).expect("sheet name failed");
+    for (dtype, cells) in &p.rcrds {
+      let fmt = match dtype.as_str() {
+        TITLE => &ftitl,
+        PAYMT => &fpaym,
+            _ => &fdrln,
+      };
+      for (i, cell) in cells.iter().enumerate() {
+        let col = i as u16;
+        let numbr = if dtype != TITLE && is_numcol(p, i)
+          { cell.trim().parse::<f64>().ok() } else { None };
+        match numbr {
+          Some(n) => { wsheet.write_number_with_format(self.nrows, col, n, fmt)
+            .expect("write failed"); },
+          None    => { wsheet.write_string_with_format(self.nrows, col, cell,
+            fmt).expect("write failed"); },
+        };
+      }
+      self.nrows += 1;
+    }
+    wsheet.set_freeze_panes(1, 0).expect("freeze failed");
+    wsheet.autofit();
+    wbook.save(&flnam).expect("creation failed");
+    flnam
+  }
+}
+
+// Tells if output column i holds amounts or rates: numeric source fields,
+// new fields other than alpha ones, and Base/TasaOCuota/Importe of the extra
+// DR tax groups (COLS layout)
+fn is_numcol(p: &Pagos2Tp, i: usize) -> bool {
+  if i >= NFLDS {
+    return [0, 3, 4].contains(&((i - NFLDS) % 5));
+  }
+  if i <= TAXCD {
+    return contains(&p.k.NUMER, &i) || i == EXCHG;
+  }
+  !contains(&p.k.ALPHA, &i)
+}